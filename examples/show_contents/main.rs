@@ -24,4 +24,9 @@ pub fn main() {
     let mut subbook = book.open_subbook(spine).unwrap();
     let title_text = subbook.read_text(Location::page(spine.index_page as u32)).unwrap();
     println!("{}", title_text.to_plaintext());
+
+    if subbook.has_copyright() {
+        println!("Copyright notice:");
+        println!("{}", subbook.copyright().unwrap().to_plaintext());
+    }
 }
@@ -1,5 +1,5 @@
 use util::CharWidthExt;
-use std::iter::FromIterator;
+use std::char;
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum Canonicalization {
@@ -18,6 +18,11 @@ impl Canonicalization {
     }
 }
 
+/// Canonicalization applied to search words before looking them up in an index.
+///
+/// For `katakana` and `lower`, `Delete` stands for conversion in the reverse
+/// direction: hiragana into katakana and upper case into lower case. For the small
+/// kana and voicing rules it has no meaning of its own and is treated as `AsIs`.
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub struct CanonicalizationRules {
     pub katakana: Canonicalization,
@@ -40,23 +45,165 @@ impl CanonicalizeExt for str {
     fn canonicalize(&self, rules: &CanonicalizationRules) -> String {
         use self::Canonicalization::*;
 
-        FromIterator::from_iter(self.chars().filter_map(|mut ch| {
-            ch = ch.to_fullwidth();
+        let mut out = String::with_capacity(self.len());
+        let mut prev = None;
+
+        for ch in self.chars() {
+            let mut ch = ch.to_fullwidth();
 
             if rules.space == Delete && ch == '\u{3000}' /* IDEOGRAPHIC SPACE */ {
-                return None;
+                continue;
+            }
+
+            match rules.katakana {
+                Convert => ch = to_hiragana(ch),
+                Delete => ch = to_katakana(ch),
+                AsIs => ()
             }
 
-            if rules.lower == Convert && ch.is_lowercase() {
+            match rules.lower {
                 // HACK
-                ch = ch.to_uppercase().next().unwrap();
+                Convert if ch.is_lowercase() => ch = ch.to_uppercase().next().unwrap(),
+                Delete if ch.is_uppercase() => ch = ch.to_lowercase().next().unwrap(),
+                _ => ()
+            }
+
+            if rules.mark == Delete && is_mark(ch) {
+                continue;
             }
 
-            return Some(ch);
-        }))
+            if ch == '\u{30fc}' /* KATAKANA-HIRAGANA PROLONGED SOUND MARK */ {
+                match rules.long_vowel {
+                    Convert => ch = prev.and_then(vowel_of).unwrap_or(ch),
+                    Delete => continue,
+                    AsIs => ()
+                }
+            }
+            prev = Some(ch);
+
+            macro_rules! small_kana(($rule:expr, $conv:expr) => (
+                if let Some(large) = $conv(ch) {
+                    match $rule {
+                        Convert => ch = large,
+                        AsIs | Delete => ()
+                    }
+                }
+            ));
+
+            small_kana!(rules.double_consonant, double_consonant);
+            small_kana!(rules.contracted_sound, contracted_sound);
+            small_kana!(rules.small_vowel, small_vowel);
+            small_kana!(rules.voiced_consonant, unvoiced);
+            small_kana!(rules.p_sound, p_sound);
+
+            out.push(ch);
+        }
+
+        out
+    }
+}
+
+fn to_hiragana(ch: char) -> char {
+    match ch {
+        '\u{30a1}'...'\u{30f6}' => char::from_u32(ch as u32 - 0x60).unwrap(),
+        _ => ch
+    }
+}
+
+fn to_katakana(ch: char) -> char {
+    match ch {
+        '\u{3041}'...'\u{3096}' => char::from_u32(ch as u32 + 0x60).unwrap(),
+        _ => ch
+    }
+}
+
+/// Apply `f` to the hiragana form of `ch`, keeping the script of `ch` in the result.
+fn kana_map<F: Fn(char) -> Option<char>>(ch: char, f: F) -> Option<char> {
+    match ch {
+        '\u{3041}'...'\u{3096}' => f(ch),
+        '\u{30a1}'...'\u{30f6}' => f(to_hiragana(ch)).map(to_katakana),
+        _ => None
+    }
+}
+
+fn is_mark(ch: char) -> bool {
+    match ch {
+        '\u{30fb}' /* ・ */ | '\u{2010}' /* ‐ */ | '\u{2019}' /* ’ */ |
+        '\u{ff07}' /* ＇ */ | '\u{2212}' /* − */ | '\u{ff0d}' /* － */ => true,
+        _ => false
+    }
+}
+
+/// Vowel of the kana `ch`, for expanding a following long vowel mark.
+fn vowel_of(ch: char) -> Option<char> {
+    static VOWELS: &'static [u8] =
+        b"aaiiuueeooaaiiuueeooaaiiuueeooaaiiuuueeooaiueoaaaiiiuuueeeoooaiueoaauuooaiueoaaieo-u";
+
+    kana_map(ch, |ch| {
+        let vowel = match ch {
+            '\u{3041}'...'\u{3094}' => VOWELS[ch as usize - 0x3041],
+            _ => return None
+        };
+
+        match vowel {
+            b'a' => Some('あ'),
+            b'i' => Some('い'),
+            b'u' => Some('う'),
+            b'e' => Some('え'),
+            b'o' => Some('お'),
+            _ => None
+        }
+    })
+}
+
+fn double_consonant(ch: char) -> Option<char> {
+    kana_map(ch, |ch| match ch {
+        'っ' => Some('つ'),
+        _ => None
+    })
+}
+
+fn contracted_sound(ch: char) -> Option<char> {
+    kana_map(ch, |ch| match ch {
+        'ゃ' | 'ゅ' | 'ょ' | 'ゎ' => char::from_u32(ch as u32 + 1),
+        _ => None
+    })
+}
+
+fn small_vowel(ch: char) -> Option<char> {
+    kana_map(ch, |ch| match ch {
+        'ぁ' | 'ぃ' | 'ぅ' | 'ぇ' | 'ぉ' => char::from_u32(ch as u32 + 1),
+        _ => None
+    })
+}
+
+fn unvoiced(ch: char) -> Option<char> {
+    match ch {
+        'ゔ' => Some('う'),
+        'ヴ' => Some('ウ'),
+        /* ヷヸヹヺ */
+        '\u{30f7}'...'\u{30fa}' => char::from_u32(ch as u32 - 8),
+        _ => kana_map(ch, |ch| {
+            let code = ch as u32;
+            let voiced = match ch {
+                'が'...'ぢ' => (code - 'か' as u32) % 2 == 1,
+                'づ'...'ど' => (code - 'つ' as u32) % 2 == 1,
+                'ば'...'ぼ' => (code - 'は' as u32) % 3 == 1,
+                _ => false
+            };
+
+            if voiced { char::from_u32(code - 1) } else { None }
+        })
     }
 }
 
+fn p_sound(ch: char) -> Option<char> {
+    kana_map(ch, |ch| match ch {
+        'ぱ'...'ぽ' if (ch as u32 - 'は' as u32) % 3 == 2 => char::from_u32(ch as u32 - 2),
+        _ => None
+    })
+}
+
 #[test]
 fn test_canonicalize() {
     let c = CanonicalizationRules {
@@ -74,3 +221,118 @@ fn test_canonicalize() {
 
     assert_eq!("environmental stress".canonicalize(&c), "ＥＮＶＩＲＯＮＭＥＮＴＡＬＳＴＲＥＳＳ")
 }
+
+#[cfg(test)]
+fn rules_with(set: &Fn(&mut CanonicalizationRules)) -> CanonicalizationRules {
+    let mut rules = CanonicalizationRules {
+        katakana: Canonicalization::AsIs,
+        lower: Canonicalization::AsIs,
+        mark: Canonicalization::AsIs,
+        long_vowel: Canonicalization::AsIs,
+        double_consonant: Canonicalization::AsIs,
+        contracted_sound: Canonicalization::AsIs,
+        small_vowel: Canonicalization::AsIs,
+        voiced_consonant: Canonicalization::AsIs,
+        p_sound: Canonicalization::AsIs,
+        space: Canonicalization::AsIs
+    };
+    set(&mut rules);
+    rules
+}
+
+#[test]
+fn test_canonicalize_katakana() {
+    let c = rules_with(&|r| r.katakana = Canonicalization::Convert);
+    assert_eq!("ガッコウ".canonicalize(&c), "がっこう");
+    let c = rules_with(&|r| r.katakana = Canonicalization::Delete);
+    assert_eq!("がっこう".canonicalize(&c), "ガッコウ");
+    let c = rules_with(&|r| r.katakana = Canonicalization::AsIs);
+    assert_eq!("ガッこう".canonicalize(&c), "ガッこう");
+}
+
+#[test]
+fn test_canonicalize_lower() {
+    let c = rules_with(&|r| r.lower = Canonicalization::Convert);
+    assert_eq!("Stress".canonicalize(&c), "ＳＴＲＥＳＳ");
+    let c = rules_with(&|r| r.lower = Canonicalization::Delete);
+    assert_eq!("Stress".canonicalize(&c), "ｓｔｒｅｓｓ");
+}
+
+#[test]
+fn test_canonicalize_mark() {
+    let c = rules_with(&|r| r.mark = Canonicalization::Delete);
+    assert_eq!("ロイド・ジョージ".canonicalize(&c), "ロイドジョージ");
+    assert_eq!("e-mail".canonicalize(&c), "ｅｍａｉｌ");
+}
+
+#[test]
+fn test_canonicalize_long_vowel() {
+    let c = rules_with(&|r| r.long_vowel = Canonicalization::Convert);
+    assert_eq!("らーめん".canonicalize(&c), "らあめん");
+    assert_eq!("ラーメン".canonicalize(&c), "ラアメン");
+    assert_eq!("ーめん".canonicalize(&c), "ーめん");
+    let c = rules_with(&|r| r.long_vowel = Canonicalization::Delete);
+    assert_eq!("らーめん".canonicalize(&c), "らめん");
+}
+
+#[test]
+fn test_canonicalize_double_consonant() {
+    let c = rules_with(&|r| r.double_consonant = Canonicalization::Convert);
+    assert_eq!("がっこう".canonicalize(&c), "がつこう");
+    let c = rules_with(&|r| r.double_consonant = Canonicalization::Delete);
+    assert_eq!("ガッコウ".canonicalize(&c), "ガッコウ");
+}
+
+#[test]
+fn test_canonicalize_contracted_sound() {
+    let c = rules_with(&|r| r.contracted_sound = Canonicalization::Convert);
+    assert_eq!("きょうしゅ".canonicalize(&c), "きようしゆ");
+    let c = rules_with(&|r| r.contracted_sound = Canonicalization::Delete);
+    assert_eq!("キャ".canonicalize(&c), "キャ");
+}
+
+#[test]
+fn test_canonicalize_small_vowel() {
+    let c = rules_with(&|r| r.small_vowel = Canonicalization::Convert);
+    assert_eq!("ファイル".canonicalize(&c), "フアイル");
+    let c = rules_with(&|r| r.small_vowel = Canonicalization::Delete);
+    assert_eq!("ふぁ".canonicalize(&c), "ふぁ");
+}
+
+#[test]
+fn test_canonicalize_voiced_consonant() {
+    let c = rules_with(&|r| r.voiced_consonant = Canonicalization::Convert);
+    assert_eq!("がぢづでばぱ".canonicalize(&c), "かちつてはぱ");
+    assert_eq!("ヴァガ".canonicalize(&c), "ウァカ");
+    let c = rules_with(&|r| r.voiced_consonant = Canonicalization::Delete);
+    assert_eq!("かご".canonicalize(&c), "かご");
+}
+
+#[test]
+fn test_canonicalize_p_sound() {
+    let c = rules_with(&|r| r.p_sound = Canonicalization::Convert);
+    assert_eq!("ぱぴぷぺぽば".canonicalize(&c), "はひふへほば");
+    assert_eq!("ペン".canonicalize(&c), "ヘン");
+    let c = rules_with(&|r| r.p_sound = Canonicalization::Delete);
+    assert_eq!("ぱん".canonicalize(&c), "ぱん");
+}
+
+#[test]
+fn test_canonicalize_space() {
+    let c = rules_with(&|r| r.space = Canonicalization::Delete);
+    assert_eq!("a b".canonicalize(&c), "ａｂ");
+    let c = rules_with(&|r| r.space = Canonicalization::AsIs);
+    assert_eq!("a b".canonicalize(&c), "ａ\u{3000}ｂ");
+}
+
+#[test]
+fn test_canonicalize_kana_index() {
+    let c = rules_with(&|r| {
+        r.katakana = Canonicalization::Convert;
+        r.long_vowel = Canonicalization::Convert;
+        r.double_consonant = Canonicalization::Convert;
+        r.voiced_consonant = Canonicalization::Convert;
+    });
+    assert_eq!("ガッコウ".canonicalize(&c), "かつこう");
+    assert_eq!("ラーメン".canonicalize(&c), "らあめん");
+}
@@ -18,8 +18,17 @@ pub struct Subbook {
     pub directory: Vec<u8>,
     pub index_page: u16,
     pub text_file: Vec<u8>,
+    pub graphic_file: Vec<u8>,
+    pub sound_file: Vec<u8>,
+    /// Narrow (half-width) external character fonts in 16, 24, 30 and 48 dot sizes.
+    pub narrow_fonts: [Option<Vec<u8>>; 4],
+    /// Wide (full-width) external character fonts in 16, 24, 30 and 48 dot sizes.
+    pub wide_fonts: [Option<Vec<u8>>; 4],
 }
 
+const CATALOG_HEADER_SIZE: u64 = 16;
+const CATALOG_ENTRY_SIZE: u64 = 164;
+
 impl Catalog {
     pub fn from_stream<R: Read+Seek>(io: &mut R) -> Result<Catalog> {
         let n_subbooks = try!(io.read_u16::<BigEndian>());
@@ -28,10 +37,20 @@ impl Catalog {
         try!(io.read_exact_(12));
 
         let mut subbooks = Vec::with_capacity(n_subbooks as usize);
-        for _ in 0..n_subbooks {
+        for i in 0..n_subbooks {
+            try!(io.seek(SeekFrom::Start(CATALOG_HEADER_SIZE + i as u64 * CATALOG_ENTRY_SIZE)));
             subbooks.push(try!(Subbook::from_stream(io)));
         }
 
+        /* EPWINGv2 and later follow the subbook entries with a filename section */
+        if epwing_version >= 2 {
+            let section = CATALOG_HEADER_SIZE + n_subbooks as u64 * CATALOG_ENTRY_SIZE;
+            for (i, subbook) in subbooks.iter_mut().enumerate() {
+                try!(io.seek(SeekFrom::Start(section + i as u64 * CATALOG_ENTRY_SIZE)));
+                try!(subbook.read_filenames(io));
+            }
+        }
+
         Ok(Catalog { epwing_version: epwing_version, subbooks: subbooks })
     }
 }
@@ -44,6 +63,23 @@ fn trim_zero_cp<'a>(slice: &'a [u8]) -> &'a [u8] {
     }
 }
 
+fn trim_name(slice: &[u8]) -> Vec<u8> {
+    let end = slice.iter().position(|&ch| ch == 0).unwrap_or(slice.len());
+    let end = slice[..end].iter().rposition(|&ch| ch != ' ' as u8).map_or(0, |i| i + 1);
+    slice[..end].to_vec()
+}
+
+fn read_font_names<R: Read>(io: &mut R) -> Result<[Option<Vec<u8>>; 4]> {
+    let mut names = [None, None, None, None];
+    for name in names.iter_mut() {
+        let raw = trim_name(&try!(io.read_exact_(8)));
+        if !raw.is_empty() {
+            *name = Some(raw);
+        }
+    }
+    Ok(names)
+}
+
 impl Subbook {
     fn from_stream<R: Read+Seek>(io: &mut R) -> Result<Subbook> {
         try!(io.seek(SeekFrom::Current(2)));
@@ -62,12 +98,68 @@ impl Subbook {
 
         let index_page = try!(io.read_u16::<BigEndian>());
 
+        try!(io.seek(SeekFrom::Current(4)));
+
+        let wide_fonts = try!(read_font_names(io));
+        let narrow_fonts = try!(read_font_names(io));
+
         Ok(Subbook {
             title: title,
             directory: directory,
             index_page: index_page,
-            /* FIXME support EPWINGv2 filename section */
-            text_file: b"HONMON".to_vec()
+            text_file: b"HONMON".to_vec(),
+            graphic_file: b"HONMON".to_vec(),
+            sound_file: b"HONMON".to_vec(),
+            narrow_fonts: narrow_fonts,
+            wide_fonts: wide_fonts,
         })
     }
+
+    fn read_filenames<R: Read+Seek>(&mut self, io: &mut R) -> Result<()> {
+        try!(io.seek(SeekFrom::Current(4)));
+        let text_file = trim_name(&try!(io.read_exact_(8)));
+
+        try!(io.seek(SeekFrom::Current(32)));
+        let graphic_file = trim_name(&try!(io.read_exact_(8)));
+
+        try!(io.seek(SeekFrom::Current(2)));
+        let sound_file = trim_name(&try!(io.read_exact_(8)));
+
+        /* Empty names mean the data is stored in the text file */
+        if !text_file.is_empty() {
+            self.text_file = text_file;
+        }
+        self.graphic_file = if graphic_file.is_empty() { self.text_file.clone() } else { graphic_file };
+        self.sound_file = if sound_file.is_empty() { self.text_file.clone() } else { sound_file };
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_filename_section() {
+    use std::io::Cursor;
+
+    let mut data = vec![0u8; 16 + 2 * 164];
+    data[1] = 1; data[3] = 2;
+
+    /* Subbook entry */
+    data[16 + 82..16 + 90].copy_from_slice(b"JMDICT  ");
+    data[16 + 95] = 3;
+    data[16 + 132..16 + 140].copy_from_slice(b"GAI16H  ");
+
+    /* Filename section */
+    let names = 16 + 164;
+    data[names + 4..names + 12].copy_from_slice(b"HONMON2 ");
+    data[names + 54..names + 62].copy_from_slice(b"SOUND\0\0\0");
+
+    let catalog = Catalog::from_stream(&mut Cursor::new(data)).unwrap();
+    let subbook = &catalog.subbooks[0];
+
+    assert_eq!(subbook.index_page, 3);
+    assert_eq!(subbook.text_file, b"HONMON2");
+    assert_eq!(subbook.graphic_file, b"HONMON2");
+    assert_eq!(subbook.sound_file, b"SOUND");
+    assert_eq!(subbook.narrow_fonts[0], Some(b"GAI16H".to_vec()));
+    assert_eq!(subbook.wide_fonts[0], None);
 }
@@ -0,0 +1,266 @@
+/* Word types, as bit masks. Words taken from text may be of any type. */
+const V1: u8 = 1 << 0;
+const V5: u8 = 1 << 1;
+const ADJ_I: u8 = 1 << 2;
+const VK: u8 = 1 << 3;
+const VS: u8 = 1 << 4;
+const TE: u8 = 1 << 5;
+/* Continuative stem, expanded into dictionary forms through STEMS */
+const STEM: u8 = 1 << 6;
+const ANY: u8 = V1 | V5 | ADJ_I | VK | VS | TE;
+
+/// A candidate dictionary form of an inflected word.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Deinflection {
+    pub word: String,
+    /// Names of the rules that lead from the inflected word to `word`, outermost
+    /// inflection first. Empty for the inflected word itself.
+    pub rules: Vec<&'static str>
+}
+
+struct Rule {
+    from: &'static str,
+    to: &'static str,
+    /// Types an inflected word must have for the rule to apply
+    from_type: u8,
+    /// Type of the word the rule produces
+    to_type: u8,
+    name: &'static str
+}
+
+macro_rules! rules {
+    ($($from:expr => $to:expr, $from_type:expr => $to_type:expr, $name:expr;)*) => {
+        &[$(Rule { from: $from, to: $to, from_type: $from_type, to_type: $to_type, name: $name }),*]
+    }
+}
+
+static RULES: &'static [Rule] = rules! {
+    "ない" => "る", ADJ_I => V1, "negative";
+    "わない" => "う", ADJ_I => V5, "negative";
+    "かない" => "く", ADJ_I => V5, "negative";
+    "がない" => "ぐ", ADJ_I => V5, "negative";
+    "さない" => "す", ADJ_I => V5, "negative";
+    "たない" => "つ", ADJ_I => V5, "negative";
+    "なない" => "ぬ", ADJ_I => V5, "negative";
+    "ばない" => "ぶ", ADJ_I => V5, "negative";
+    "まない" => "む", ADJ_I => V5, "negative";
+    "らない" => "る", ADJ_I => V5, "negative";
+    "こない" => "くる", ADJ_I => VK, "negative";
+    "しない" => "する", ADJ_I => VS, "negative";
+    "くない" => "い", ADJ_I => ADJ_I, "negative";
+
+    "た" => "る", ANY => V1, "past";
+    "った" => "う", ANY => V5, "past";
+    "った" => "つ", ANY => V5, "past";
+    "った" => "る", ANY => V5, "past";
+    "いた" => "く", ANY => V5, "past";
+    "いだ" => "ぐ", ANY => V5, "past";
+    "した" => "す", ANY => V5, "past";
+    "んだ" => "ぬ", ANY => V5, "past";
+    "んだ" => "ぶ", ANY => V5, "past";
+    "んだ" => "む", ANY => V5, "past";
+    "行った" => "行く", ANY => V5, "past";
+    "いった" => "いく", ANY => V5, "past";
+    "きた" => "くる", ANY => VK, "past";
+    "した" => "する", ANY => VS, "past";
+    "かった" => "い", ANY => ADJ_I, "past";
+
+    "て" => "る", TE => V1, "te";
+    "って" => "う", TE => V5, "te";
+    "って" => "つ", TE => V5, "te";
+    "って" => "る", TE => V5, "te";
+    "いて" => "く", TE => V5, "te";
+    "いで" => "ぐ", TE => V5, "te";
+    "して" => "す", TE => V5, "te";
+    "んで" => "ぬ", TE => V5, "te";
+    "んで" => "ぶ", TE => V5, "te";
+    "んで" => "む", TE => V5, "te";
+    "行って" => "行く", TE => V5, "te";
+    "いって" => "いく", TE => V5, "te";
+    "きて" => "くる", TE => VK, "te";
+    "して" => "する", TE => VS, "te";
+    "くて" => "い", TE => ADJ_I, "te";
+
+    "たら" => "た", ANY => ANY, "conditional";
+    "だら" => "だ", ANY => ANY, "conditional";
+    "たり" => "た", ANY => ANY, "-tari";
+    "だり" => "だ", ANY => ANY, "-tari";
+
+    "れば" => "る", ANY => V1, "provisional";
+    "えば" => "う", ANY => V5, "provisional";
+    "けば" => "く", ANY => V5, "provisional";
+    "げば" => "ぐ", ANY => V5, "provisional";
+    "せば" => "す", ANY => V5, "provisional";
+    "てば" => "つ", ANY => V5, "provisional";
+    "ねば" => "ぬ", ANY => V5, "provisional";
+    "べば" => "ぶ", ANY => V5, "provisional";
+    "めば" => "む", ANY => V5, "provisional";
+    "れば" => "る", ANY => V5, "provisional";
+    "くれば" => "くる", ANY => VK, "provisional";
+    "すれば" => "する", ANY => VS, "provisional";
+    "ければ" => "い", ANY => ADJ_I, "provisional";
+
+    "よう" => "る", ANY => V1, "volitional";
+    "おう" => "う", ANY => V5, "volitional";
+    "こう" => "く", ANY => V5, "volitional";
+    "ごう" => "ぐ", ANY => V5, "volitional";
+    "そう" => "す", ANY => V5, "volitional";
+    "とう" => "つ", ANY => V5, "volitional";
+    "のう" => "ぬ", ANY => V5, "volitional";
+    "ぼう" => "ぶ", ANY => V5, "volitional";
+    "もう" => "む", ANY => V5, "volitional";
+    "ろう" => "る", ANY => V5, "volitional";
+    "こよう" => "くる", ANY => VK, "volitional";
+    "しよう" => "する", ANY => VS, "volitional";
+
+    "ろ" => "る", ANY => V1, "imperative";
+    "よ" => "る", ANY => V1, "imperative";
+    "え" => "う", ANY => V5, "imperative";
+    "け" => "く", ANY => V5, "imperative";
+    "げ" => "ぐ", ANY => V5, "imperative";
+    "せ" => "す", ANY => V5, "imperative";
+    "て" => "つ", ANY => V5, "imperative";
+    "ね" => "ぬ", ANY => V5, "imperative";
+    "べ" => "ぶ", ANY => V5, "imperative";
+    "め" => "む", ANY => V5, "imperative";
+    "れ" => "る", ANY => V5, "imperative";
+    "こい" => "くる", ANY => VK, "imperative";
+    "しろ" => "する", ANY => VS, "imperative";
+    "せよ" => "する", ANY => VS, "imperative";
+
+    "られる" => "る", V1 => V1, "potential or passive";
+    "える" => "う", V1 => V5, "potential";
+    "ける" => "く", V1 => V5, "potential";
+    "げる" => "ぐ", V1 => V5, "potential";
+    "せる" => "す", V1 => V5, "potential";
+    "てる" => "つ", V1 => V5, "potential";
+    "ねる" => "ぬ", V1 => V5, "potential";
+    "べる" => "ぶ", V1 => V5, "potential";
+    "める" => "む", V1 => V5, "potential";
+    "れる" => "る", V1 => V5, "potential";
+    "こられる" => "くる", V1 => VK, "potential or passive";
+
+    "われる" => "う", V1 => V5, "passive";
+    "かれる" => "く", V1 => V5, "passive";
+    "がれる" => "ぐ", V1 => V5, "passive";
+    "される" => "す", V1 => V5, "passive";
+    "たれる" => "つ", V1 => V5, "passive";
+    "なれる" => "ぬ", V1 => V5, "passive";
+    "ばれる" => "ぶ", V1 => V5, "passive";
+    "まれる" => "む", V1 => V5, "passive";
+    "られる" => "る", V1 => V5, "passive";
+    "される" => "する", V1 => VS, "passive";
+
+    "させる" => "る", V1 => V1, "causative";
+    "わせる" => "う", V1 => V5, "causative";
+    "かせる" => "く", V1 => V5, "causative";
+    "がせる" => "ぐ", V1 => V5, "causative";
+    "させる" => "す", V1 => V5, "causative";
+    "たせる" => "つ", V1 => V5, "causative";
+    "なせる" => "ぬ", V1 => V5, "causative";
+    "ばせる" => "ぶ", V1 => V5, "causative";
+    "ませる" => "む", V1 => V5, "causative";
+    "らせる" => "る", V1 => V5, "causative";
+    "こさせる" => "くる", V1 => VK, "causative";
+    "させる" => "する", V1 => VS, "causative";
+
+    "ている" => "て", V1 => TE, "progressive";
+    "でいる" => "で", V1 => TE, "progressive";
+    "てる" => "て", V1 => TE, "progressive";
+    "でる" => "で", V1 => TE, "progressive";
+    "てしまう" => "て", V5 => TE, "-shimau";
+    "でしまう" => "で", V5 => TE, "-shimau";
+
+    "ます" => "", ANY => STEM, "polite";
+    "ました" => "", ANY => STEM, "polite past";
+    "ません" => "", ANY => STEM, "polite negative";
+    "ませんでした" => "", ANY => STEM, "polite past negative";
+    "ましょう" => "", ANY => STEM, "polite volitional";
+    "たい" => "", ADJ_I => STEM, "-tai";
+    "なさい" => "", ANY => STEM, "-nasai";
+
+    "く" => "い", ANY => ADJ_I, "adverb";
+    "さ" => "い", ANY => ADJ_I, "noun";
+};
+
+/* Endings of continuative stems and the dictionary forms they belong to */
+static STEMS: &'static [(&'static str, &'static str, u8)] = &[
+    ("", "る", V1),
+    ("い", "う", V5), ("き", "く", V5), ("ぎ", "ぐ", V5), ("し", "す", V5), ("ち", "つ", V5),
+    ("に", "ぬ", V5), ("び", "ぶ", V5), ("み", "む", V5), ("り", "る", V5),
+    ("き", "くる", VK), ("し", "する", VS)
+];
+
+/// Generate every candidate dictionary form of `word`, starting with `word` itself.
+///
+/// Candidates are produced by suffix rules alone, so most of them are not real words;
+/// they are meant to be looked up in an index.
+pub fn deinflect(word: &str) -> Vec<Deinflection> {
+    let mut candidates = vec![(Deinflection { word: word.to_string(), rules: vec![] }, ANY)];
+    let mut i = 0;
+
+    while i < candidates.len() {
+        let mut found = vec![];
+
+        {
+            let (ref current, word_type) = candidates[i];
+
+            for rule in RULES.iter() {
+                if word_type & rule.from_type == 0 || !current.word.ends_with(rule.from) {
+                    continue;
+                }
+
+                let stem = &current.word[..current.word.len() - rule.from.len()];
+                let mut rules = current.rules.clone();
+                rules.push(rule.name);
+
+                if rule.to_type == STEM {
+                    for &(ending, to, to_type) in STEMS.iter() {
+                        if (stem.ends_with(ending) && stem.len() > ending.len()) ||
+                           (stem == ending && (to_type == VK || to_type == VS))
+                        {
+                            let word = format!("{}{}", &stem[..stem.len() - ending.len()], to);
+                            found.push((word, rules.clone(), to_type));
+                        }
+                    }
+                } else if !stem.is_empty() || rule.to_type == VK || rule.to_type == VS {
+                    found.push((format!("{}{}", stem, rule.to), rules, rule.to_type));
+                }
+            }
+        }
+
+        for (word, rules, word_type) in found {
+            if !candidates.iter().any(|&(ref c, t)| c.word == word && t == word_type) {
+                candidates.push((Deinflection { word: word, rules: rules }, word_type));
+            }
+        }
+
+        i += 1;
+    }
+
+    /* The same word may be reached with different types; keep the first, shortest chain */
+    let mut out: Vec<Deinflection> = vec![];
+    for (candidate, _) in candidates {
+        if !out.iter().any(|c| c.word == candidate.word) {
+            out.push(candidate);
+        }
+    }
+
+    out
+}
+
+#[test]
+fn test_deinflect() {
+    fn rules_for(word: &str, form: &str) -> Option<Vec<&'static str>> {
+        deinflect(word).into_iter().find(|c| c.word == form).map(|c| c.rules)
+    }
+
+    assert_eq!(deinflect("食べる")[0], Deinflection { word: "食べる".to_string(), rules: vec![] });
+    assert_eq!(rules_for("食べなかった", "食べる"), Some(vec!["past", "negative"]));
+    assert_eq!(rules_for("美しくて", "美しい"), Some(vec!["te"]));
+    assert_eq!(rules_for("行きます", "行く"), Some(vec!["polite"]));
+    assert_eq!(rules_for("読んでいた", "読む"), Some(vec!["past", "progressive", "te"]));
+    assert_eq!(rules_for("勉強しています", "勉強する"), Some(vec!["polite", "progressive", "te"]));
+    assert_eq!(rules_for("しない", "する"), Some(vec!["negative"]));
+    assert_eq!(rules_for("ない", "る"), None);
+}
@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+
+use subbook::Location;
+use util::ReadExact;
+
+use Error;
+use Result;
+
+static MAGIC: &'static [u8] = b"EPFT";
+const VERSION: u32 = 1;
+
+/// An inverted index of character bigrams in the body text of a subbook, used to answer
+/// full text searches without scanning the text.
+///
+/// The index remembers the sizes of the CATALOGS and text files it was built from,
+/// so that an index saved for an older version of a book can be detected.
+///
+/// The posting lists are held in memory in full, about six bytes for each entry a
+/// bigram occurs in, so a saved index is read completely before it can be queried.
+#[derive(Debug, Clone)]
+pub struct FulltextIndex {
+    catalog_size: u64,
+    text_size: u64,
+    postings: BTreeMap<String, Vec<Location>>
+}
+
+fn grams(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+
+    (0..chars.len()).map(|i| chars[i..(i + 2).min(chars.len())].iter().cloned().collect())
+                    .collect()
+}
+
+fn location_key(location: &Location) -> (u32, u16) {
+    (location.page, location.offset)
+}
+
+fn union(lists: Vec<&Vec<Location>>) -> Vec<Location> {
+    let mut out: Vec<Location> = lists.into_iter().flat_map(|list| list.iter().cloned()).collect();
+    out.sort_by(|a, b| location_key(a).cmp(&location_key(b)));
+    out.dedup();
+    out
+}
+
+impl FulltextIndex {
+    pub fn new(catalog_size: u64, text_size: u64) -> FulltextIndex {
+        FulltextIndex {
+            catalog_size: catalog_size,
+            text_size: text_size,
+            postings: BTreeMap::new()
+        }
+    }
+
+    /// Whether the index was built from files of different sizes than given.
+    pub fn is_stale(&self, catalog_size: u64, text_size: u64) -> bool {
+        self.catalog_size != catalog_size || self.text_size != text_size
+    }
+
+    /// Add the text of the entry at `location`. Entries must be added in text order.
+    pub fn insert(&mut self, location: Location, text: &str) {
+        for gram in grams(text) {
+            let list = self.postings.entry(gram).or_insert(vec![]);
+            if list.last() != Some(&location) {
+                list.push(location);
+            }
+        }
+    }
+
+    /// Locations of entries that may contain `pattern`, in text order. The entry text
+    /// has to be checked to rule out false positives.
+    pub fn candidates(&self, pattern: &str) -> Vec<Location> {
+        let grams = grams(pattern);
+
+        match grams.len() {
+            0 => union(self.postings.values().collect()),
+            /* A single character, starting any bigram */
+            1 => union(self.postings.range(grams[0].clone()..)
+                                     .take_while(|&(gram, _)| gram.starts_with(&grams[0][..]))
+                                     .map(|(_, list)| list).collect()),
+            n => {
+                let mut out = match self.postings.get(&grams[0]) {
+                    Some(list) => list.clone(),
+                    None => return vec![]
+                };
+
+                for gram in grams[1..n - 1].iter() {
+                    let list = match self.postings.get(gram) {
+                        Some(list) => list,
+                        None => return vec![]
+                    };
+                    out.retain(|location| list.binary_search_by(|other| {
+                        location_key(other).cmp(&location_key(location))
+                    }).is_ok());
+                }
+
+                out
+            }
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        try!(w.write_all(MAGIC));
+        try!(w.write_u32::<BigEndian>(VERSION));
+        try!(w.write_u64::<BigEndian>(self.catalog_size));
+        try!(w.write_u64::<BigEndian>(self.text_size));
+        try!(w.write_u32::<BigEndian>(self.postings.len() as u32));
+
+        for (gram, list) in self.postings.iter() {
+            try!(w.write_u8(gram.len() as u8));
+            try!(w.write_all(gram.as_bytes()));
+            try!(w.write_u32::<BigEndian>(list.len() as u32));
+            for location in list.iter() {
+                try!(w.write_u32::<BigEndian>(location.page));
+                try!(w.write_u16::<BigEndian>(location.offset));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<FulltextIndex> {
+        let magic = try!(r.read_exact_(4));
+        if magic != MAGIC || try!(r.read_u32::<BigEndian>()) != VERSION {
+            return Err(Error::InvalidFormat);
+        }
+
+        let mut index = FulltextIndex::new(try!(r.read_u64::<BigEndian>()),
+                                           try!(r.read_u64::<BigEndian>()));
+        let n_grams = try!(r.read_u32::<BigEndian>());
+
+        for _ in 0..n_grams {
+            let len = try!(r.read_u8());
+            let gram = try!(r.read_exact_(len as u64));
+            let gram = try!(String::from_utf8(gram).map_err(|_| Error::InvalidFormat));
+
+            /* The count comes from the file, so it is not trusted for allocating */
+            let n_locations = try!(r.read_u32::<BigEndian>());
+            let mut list = vec![];
+            for _ in 0..n_locations {
+                list.push(Location {
+                    page: try!(r.read_u32::<BigEndian>()),
+                    offset: try!(r.read_u16::<BigEndian>())
+                });
+            }
+
+            index.postings.insert(gram, list);
+        }
+
+        Ok(index)
+    }
+}
+
+#[test]
+fn test_fulltext_index() {
+    use std::io::Cursor;
+
+    let mut index = FulltextIndex::new(10, 20);
+    index.insert(Location::page(1), "環境問題");
+    index.insert(Location::page(2), "問題集");
+    index.insert(Location::page(3), "環境");
+
+    assert_eq!(index.candidates("問題"), [Location::page(1), Location::page(2)]);
+    assert_eq!(index.candidates("環境問"), [Location::page(1)]);
+    assert_eq!(index.candidates("境"), [Location::page(1), Location::page(3)]);
+    assert_eq!(index.candidates("集").len(), 1);
+    assert!(index.candidates("境集").is_empty());
+
+    let mut data = vec![];
+    index.write_to(&mut data).unwrap();
+    let index = FulltextIndex::read_from(&mut Cursor::new(data)).unwrap();
+
+    assert_eq!(index.candidates("環境問"), [Location::page(1)]);
+    assert!(!index.is_stale(10, 20));
+    assert!(index.is_stale(10, 21));
+
+    /* A truncated file claiming a huge posting list */
+    let mut data = vec![];
+    FulltextIndex::new(10, 20).write_to(&mut data).unwrap();
+    data[27] = 1;
+    data.extend([1, b'A', 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 1].iter().cloned());
+    match FulltextIndex::read_from(&mut Cursor::new(data)) {
+        Err(Error::InvalidFormat) => (),
+        r => panic!("unexpected result {:?}", r)
+    }
+}
@@ -1,5 +1,6 @@
 extern crate jis0208;
 extern crate unicode_hfwidth;
+extern crate unicode_normalization;
 extern crate byteorder;
 
 use std::io::Error as IoError;
@@ -8,26 +9,42 @@ use catalog::Catalog;
 use subbook::Subbook;
 
 pub use subbook::ToPlaintext as ToPlaintext;
+pub use canon::{Canonicalization, CanonicalizationRules};
+pub use romaji::Kana;
+pub use deinflect::Deinflection;
+pub use fulltext::FulltextIndex;
 
 pub mod catalog;
 pub mod subbook;
 
 mod util;
 mod canon;
+mod romaji;
+mod deinflect;
+mod fulltext;
 
 #[derive(Debug)]
 pub enum Error {
     Io(IoError),
     InvalidEncoding,
     InvalidFormat,
-    IndexNotAvailable
+    IndexNotAvailable,
+    UnencodableCharacters(Vec<char>),
+    StaleIndex,
+    InvalidArgument
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
-        use std::error::Error;
+        use std::error::Error as StdError;
 
-        write!(fmt, "EPWING error: {}", self.description())
+        match *self {
+            Error::UnencodableCharacters(ref chars) => {
+                let chars: String = chars.iter().cloned().collect();
+                write!(fmt, "EPWING error: {}: {}", self.description(), chars)
+            },
+            _ => write!(fmt, "EPWING error: {}", self.description())
+        }
     }
 }
 
@@ -38,6 +55,9 @@ impl std::error::Error for Error {
             Error::InvalidEncoding => "encountered non-JIS X 0208 character",
             Error::InvalidFormat => "file is malformed",
             Error::IndexNotAvailable => "requested index is not available",
+            Error::UnencodableCharacters(_) => "search word contains characters not in JIS X 0208",
+            Error::StaleIndex => "full text index was built from different book files",
+            Error::InvalidArgument => "invalid argument",
         }
     }
 
@@ -66,9 +86,18 @@ impl From<byteorder::Error> for Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// A search result from `Book::search_all`, with the number of the subbook it was
+/// found in, as in `Book::subbooks`.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct BookSearchHit {
+    pub subbook: usize,
+    pub hit: subbook::SearchHit
+}
+
 pub struct Book {
     path: std::path::PathBuf,
-    catalog: Catalog
+    catalog: Catalog,
+    opened: Vec<Option<Subbook>>
 }
 
 impl Book {
@@ -76,9 +105,12 @@ impl Book {
         let mut catalog_fp = try!(std::fs::File::open(&path.join("CATALOGS")));
         let catalog = try!(Catalog::from_stream(&mut catalog_fp));
 
+        let opened = catalog.subbooks.iter().map(|_| None).collect();
+
         Ok(Book {
             catalog: catalog,
-            path: path
+            path: path,
+            opened: opened
         })
     }
 
@@ -87,6 +119,97 @@ impl Book {
     }
 
     pub fn open_subbook(&self, subbook: &catalog::Subbook) -> Result<Subbook> {
+        let fp = try!(std::fs::File::open(&try!(self.text_path(subbook))));
+
+        /* Text and index entries are read a few bytes at a time */
+        subbook::Subbook::from_io(std::io::BufReader::new(fp))
+    }
+
+    /// The subbook numbered `i`, as in `subbooks`, opened on first use and kept open.
+    /// Search options set on it apply to `search_all` as well.
+    ///
+    /// Panics if `i` is out of range.
+    pub fn subbook_mut(&mut self, i: usize) -> Result<&mut Subbook> {
+        if self.opened[i].is_none() {
+            let sbook = try!(self.open_subbook(&self.catalog.subbooks[i]));
+            self.opened[i] = Some(sbook);
+        }
+
+        Ok(self.opened[i].as_mut().unwrap())
+    }
+
+    /// Search every subbook having the given index. Subbooks are opened as with
+    /// `subbook_mut`; those that cannot be opened are left out, unless none can be,
+    /// in which case the first error is returned.
+    pub fn search_all(&mut self, index: subbook::Index, word: &str) -> Result<Vec<BookSearchHit>> {
+        let mut results = vec![];
+        let mut available = false;
+        let mut searched = false;
+        let mut open_error = None;
+
+        for i in 0..self.catalog.subbooks.len() {
+            let sbook = match self.subbook_mut(i) {
+                Ok(sbook) => sbook,
+                Err(e) => {
+                    open_error = open_error.or(Some(e));
+                    continue;
+                }
+            };
+            searched = true;
+
+            match sbook.search(index, word) {
+                Ok(hits) => {
+                    available = true;
+                    results.extend(hits.into_iter().map(|hit| BookSearchHit { subbook: i, hit: hit }));
+                },
+                Err(Error::IndexNotAvailable) => (),
+                Err(e) => return Err(e)
+            }
+        }
+
+        match open_error {
+            _ if available => Ok(results),
+            Some(e) if !searched => Err(e),
+            _ => Err(Error::IndexNotAvailable)
+        }
+    }
+
+    /// Build a full text index of a subbook and save it to `index_path`.
+    pub fn build_fulltext_index(&self, subbook: &catalog::Subbook, index_path: &std::path::Path)
+        -> Result<()>
+    {
+        use std::io::Write;
+
+        let (catalog_size, text_size) = try!(self.file_sizes(subbook));
+        let index = try!(try!(self.open_subbook(subbook)).build_fulltext_index(catalog_size, text_size));
+
+        let mut fp = std::io::BufWriter::new(try!(std::fs::File::create(index_path)));
+        try!(index.write_to(&mut fp));
+        try!(fp.flush());
+
+        Ok(())
+    }
+
+    /// Load a full text index saved with `build_fulltext_index`. Fails with
+    /// `Error::StaleIndex` if the book files have changed since.
+    ///
+    /// The whole index is read into memory, which for a large book can take as much
+    /// memory as the index file is big; keep it around for further queries.
+    pub fn open_fulltext_index(&self, subbook: &catalog::Subbook, index_path: &std::path::Path)
+        -> Result<FulltextIndex>
+    {
+        let mut fp = std::io::BufReader::new(try!(std::fs::File::open(index_path)));
+        let index = try!(FulltextIndex::read_from(&mut fp));
+
+        let (catalog_size, text_size) = try!(self.file_sizes(subbook));
+        if index.is_stale(catalog_size, text_size) {
+            return Err(Error::StaleIndex);
+        }
+
+        Ok(index)
+    }
+
+    fn text_path(&self, subbook: &catalog::Subbook) -> Result<std::path::PathBuf> {
         use std::ffi::OsStr;
         use std::os::unix::ffi::OsStrExt;
 
@@ -94,11 +217,126 @@ impl Book {
                                                         .ok_or(Error::InvalidFormat));
         let dir_path = &subbook.directory[..last_nonws_i+1];
 
-        let path = self.path.join(OsStr::from_bytes(dir_path)).join("DATA")
-                            .join(OsStr::from_bytes(&subbook.text_file));
-        let fp = try!(std::fs::File::open(&path));
+        Ok(self.path.join(OsStr::from_bytes(dir_path)).join("DATA")
+                    .join(OsStr::from_bytes(&subbook.text_file)))
+    }
+
+    fn file_sizes(&self, subbook: &catalog::Subbook) -> Result<(u64, u64)> {
+        let catalog_size = try!(std::fs::metadata(&self.path.join("CATALOGS"))).len();
+        let text_size = try!(std::fs::metadata(&try!(self.text_path(subbook)))).len();
 
-        subbook::Subbook::from_io(fp)
+        Ok((catalog_size, text_size))
     }
 }
 
+/// Write a book with one subbook per entry of `subbooks` to a fresh directory named
+/// `name` in the temporary directory. Each subbook is given as its directory name and
+/// the contents of its text file.
+#[cfg(test)]
+fn write_test_book(name: &str, subbooks: &[(&str, &[u8])]) -> std::path::PathBuf {
+    use std::io::Write;
+
+    let path = std::env::temp_dir().join(name);
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+
+    let mut catalog = vec![0u8; 16 + subbooks.len() * 164];
+    catalog[1] = subbooks.len() as u8;
+    catalog[3] = 1;
+
+    for (i, &(directory, text)) in subbooks.iter().enumerate() {
+        let entry = 16 + i * 164;
+        catalog[entry + 82..entry + 90].copy_from_slice(format!("{:8}", directory).as_bytes());
+
+        let data_path = path.join(directory).join("DATA");
+        std::fs::create_dir_all(&data_path).unwrap();
+        std::fs::File::create(data_path.join("HONMON")).unwrap().write_all(text).unwrap();
+    }
+
+    std::fs::File::create(path.join("CATALOGS")).unwrap().write_all(&catalog).unwrap();
+    path
+}
+
+/// Text file of a subbook with one entry of body text and a word kana index holding
+/// the heading of the entry.
+#[cfg(test)]
+fn test_text_file(heading: &str) -> Vec<u8> {
+    use util::ToJisString;
+
+    let mut data = vec![0u8; 3 * 0x800];
+    let heading = heading.to_jis_string().unwrap();
+
+    /* Index table: body text at page 2, word kana index at page 3 */
+    data[1] = 2;
+    data[16..26].copy_from_slice(&[0x00, 0, 0, 0, 0, 2, 0, 0, 0, 1]);
+    data[32..42].copy_from_slice(&[0x90, 0, 0, 0, 0, 3, 0, 0, 0, 1]);
+
+    let mut body = vec![0x1f, 0x02, 0x1f, 0x41, 0x00, 0x01];
+    body.extend(heading.iter().cloned());
+    body.extend([0x1f, 0x61, 0x1f, 0x03].iter().cloned());
+    data[0x800..0x800 + body.len()].copy_from_slice(&body);
+
+    let mut leaf = vec![0xa0, 0, 0, 1, heading.len() as u8];
+    leaf.extend(heading.iter().cloned());
+    leaf.extend([0, 0, 0, 2, 0, 0, 0, 0, 0, 2, 0, 0].iter().cloned());
+    data[0x1000..0x1000 + leaf.len()].copy_from_slice(&leaf);
+
+    data
+}
+
+#[test]
+fn test_stale_fulltext_index() {
+    use std::io::Write;
+
+    let path = write_test_book("epwing-test-stale-index", &[("BOOK", &test_text_file("ＡＡ"))]);
+    let index_path = path.join("index");
+
+    let book = Book::open(path.clone()).unwrap();
+    let spine = &book.subbooks()[0];
+    book.build_fulltext_index(spine, &index_path).unwrap();
+    assert!(book.open_fulltext_index(spine, &index_path).is_ok());
+
+    let mut fp = std::fs::OpenOptions::new().append(true).open(path.join("BOOK/DATA/HONMON")).unwrap();
+    fp.write_all(&[0; 0x800]).unwrap();
+
+    match book.open_fulltext_index(spine, &index_path) {
+        Err(Error::StaleIndex) => (),
+        r => panic!("unexpected result {:?}", r)
+    }
+}
+
+#[test]
+fn test_search_all() {
+    use subbook::{Index, Location};
+
+    /* The second subbook has an empty text file and cannot be opened */
+    let path = write_test_book("epwing-test-search-all", &[("BOOK1", &test_text_file("ああ")),
+                                                           ("BOOK2", &[]),
+                                                           ("BOOK3", &test_text_file("ああ"))]);
+    let mut book = Book::open(path).unwrap();
+
+    let subbooks = |hits: Vec<BookSearchHit>| {
+        hits.into_iter().map(|hit| hit.subbook).collect::<Vec<_>>()
+    };
+
+    let hits = book.search_all(Index::WordKana, "ああ").unwrap();
+    assert_eq!(subbooks(hits.clone()), [0, 2]);
+    assert_eq!(hits[0].hit.text, Location::page(1));
+    assert!(book.subbook_mut(1).is_err());
+
+    /* Options set on a cached subbook are kept */
+    book.subbook_mut(0).unwrap().set_romaji_conversion(Some(Kana::Hiragana));
+    assert_eq!(subbooks(book.search_all(Index::WordKana, "aa").unwrap()), [0]);
+
+    match book.search_all(Index::WordAsIs, "ああ") {
+        Err(Error::IndexNotAvailable) => (),
+        r => panic!("unexpected result {:?}", r)
+    }
+
+    /* With no subbook to search, the reason is passed on */
+    let path = write_test_book("epwing-test-search-all-broken", &[("BOOK1", &[])]);
+    match Book::open(path).unwrap().search_all(Index::WordKana, "ああ") {
+        Err(Error::InvalidFormat) => (),
+        r => panic!("unexpected result {:?}", r)
+    }
+}
@@ -0,0 +1,133 @@
+use std::char;
+
+/// Kana script to convert romaji into.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Kana {
+    Hiragana,
+    Katakana
+}
+
+static SYLLABLES: &'static [(&'static str, &'static str)] = &[
+    ("a", "あ"), ("i", "い"), ("u", "う"), ("e", "え"), ("o", "お"),
+    ("ka", "か"), ("ki", "き"), ("ku", "く"), ("ke", "け"), ("ko", "こ"),
+    ("kya", "きゃ"), ("kyu", "きゅ"), ("kyo", "きょ"),
+    ("ga", "が"), ("gi", "ぎ"), ("gu", "ぐ"), ("ge", "げ"), ("go", "ご"),
+    ("gya", "ぎゃ"), ("gyu", "ぎゅ"), ("gyo", "ぎょ"),
+    ("sa", "さ"), ("si", "し"), ("shi", "し"), ("su", "す"), ("se", "せ"), ("so", "そ"),
+    ("sha", "しゃ"), ("shu", "しゅ"), ("she", "しぇ"), ("sho", "しょ"),
+    ("sya", "しゃ"), ("syu", "しゅ"), ("syo", "しょ"),
+    ("za", "ざ"), ("zi", "じ"), ("ji", "じ"), ("zu", "ず"), ("ze", "ぜ"), ("zo", "ぞ"),
+    ("ja", "じゃ"), ("ju", "じゅ"), ("je", "じぇ"), ("jo", "じょ"),
+    ("zya", "じゃ"), ("zyu", "じゅ"), ("zyo", "じょ"),
+    ("jya", "じゃ"), ("jyu", "じゅ"), ("jyo", "じょ"),
+    ("ta", "た"), ("ti", "ち"), ("chi", "ち"), ("tu", "つ"), ("tsu", "つ"), ("te", "て"), ("to", "と"),
+    ("cha", "ちゃ"), ("chu", "ちゅ"), ("che", "ちぇ"), ("cho", "ちょ"),
+    ("tya", "ちゃ"), ("tyu", "ちゅ"), ("tyo", "ちょ"),
+    ("da", "だ"), ("di", "ぢ"), ("du", "づ"), ("de", "で"), ("do", "ど"),
+    ("dya", "ぢゃ"), ("dyu", "ぢゅ"), ("dyo", "ぢょ"),
+    ("na", "な"), ("ni", "に"), ("nu", "ぬ"), ("ne", "ね"), ("no", "の"),
+    ("nya", "にゃ"), ("nyu", "にゅ"), ("nyo", "にょ"),
+    ("ha", "は"), ("hi", "ひ"), ("hu", "ふ"), ("fu", "ふ"), ("he", "へ"), ("ho", "ほ"),
+    ("hya", "ひゃ"), ("hyu", "ひゅ"), ("hyo", "ひょ"),
+    ("fa", "ふぁ"), ("fi", "ふぃ"), ("fe", "ふぇ"), ("fo", "ふぉ"),
+    ("ba", "ば"), ("bi", "び"), ("bu", "ぶ"), ("be", "べ"), ("bo", "ぼ"),
+    ("bya", "びゃ"), ("byu", "びゅ"), ("byo", "びょ"),
+    ("pa", "ぱ"), ("pi", "ぴ"), ("pu", "ぷ"), ("pe", "ぺ"), ("po", "ぽ"),
+    ("pya", "ぴゃ"), ("pyu", "ぴゅ"), ("pyo", "ぴょ"),
+    ("ma", "ま"), ("mi", "み"), ("mu", "む"), ("me", "め"), ("mo", "も"),
+    ("mya", "みゃ"), ("myu", "みゅ"), ("myo", "みょ"),
+    ("ya", "や"), ("yu", "ゆ"), ("yo", "よ"),
+    ("ra", "ら"), ("ri", "り"), ("ru", "る"), ("re", "れ"), ("ro", "ろ"),
+    ("rya", "りゃ"), ("ryu", "りゅ"), ("ryo", "りょ"),
+    ("wa", "わ"), ("wo", "を"), ("vu", "ゔ")
+];
+
+fn is_consonant(ch: char) -> bool {
+    ch >= 'a' && ch <= 'z' && !"aiueo".contains(ch)
+}
+
+pub trait RomajiExt {
+    fn romaji_to_kana(&self, kana: Kana) -> String;
+}
+
+impl RomajiExt for str {
+    /// Convert Hepburn or Kunrei-shiki romaji into kana. Other characters,
+    /// including kana, are kept as they are.
+    fn romaji_to_kana(&self, kana: Kana) -> String {
+        /* Long vowels written with a macron or circumflex */
+        let mut input = vec![];
+        for ch in self.chars().flat_map(|ch| ch.to_lowercase()) {
+            match ch {
+                'ā' | 'â' => input.extend(['a', 'a'].iter().cloned()),
+                'ī' | 'î' => input.extend(['i', 'i'].iter().cloned()),
+                'ū' | 'û' => input.extend(['u', 'u'].iter().cloned()),
+                'ē' | 'ê' => input.extend(['e', 'e'].iter().cloned()),
+                'ō' | 'ô' => input.extend(['o', 'u'].iter().cloned()),
+                _ => input.push(ch)
+            }
+        }
+
+        let mut out = String::with_capacity(self.len() * 3);
+        let mut i = 0;
+
+        while i < input.len() {
+            let ch = input[i];
+            let next = input.get(i + 1).cloned();
+
+            /* Doubled consonants, and "tch" in Hepburn */
+            if (next == Some(ch) && ch != 'n' && is_consonant(ch)) ||
+               (ch == 't' && next == Some('c') && input.get(i + 2) == Some(&'h'))
+            {
+                out.push('っ');
+                i += 1;
+                continue;
+            }
+
+            let syllable = (1..4).rev().filter(|&n| i + n <= input.len()).filter_map(|n| {
+                let romaji: String = input[i..i + n].iter().cloned().collect();
+                SYLLABLES.iter().find(|&&(r, _)| r == romaji).map(|&(_, k)| (k, n))
+            }).next();
+
+            match (syllable, ch) {
+                (Some((k, n)), _) => {
+                    out.push_str(k);
+                    i += n;
+                    continue;
+                },
+                (None, 'n') => {
+                    out.push('ん');
+                    if next == Some('\'') {
+                        i += 1;
+                    }
+                },
+                /* Hepburn writes ん as m before labials */
+                (None, 'm') if next.map_or(false, |n| "bmp".contains(n)) => out.push('ん'),
+                (None, '-') => out.push('ー'),
+                (None, ch) => out.push(ch)
+            }
+
+            i += 1;
+        }
+
+        match kana {
+            Kana::Hiragana => out,
+            Kana::Katakana => out.chars().map(|ch| match ch {
+                '\u{3041}'...'\u{3096}' => char::from_u32(ch as u32 + 0x60).unwrap(),
+                _ => ch
+            }).collect()
+        }
+    }
+}
+
+#[test]
+fn test_romaji_to_kana() {
+    assert_eq!("gakkou".romaji_to_kana(Kana::Hiragana), "がっこう");
+    assert_eq!("kyoudai".romaji_to_kana(Kana::Hiragana), "きょうだい");
+    assert_eq!("Tōkyō".romaji_to_kana(Kana::Hiragana), "とうきょう");
+    assert_eq!("konnichiha".romaji_to_kana(Kana::Hiragana), "こんにちは");
+    assert_eq!("hon'ya".romaji_to_kana(Kana::Hiragana), "ほんや");
+    assert_eq!("shimbun".romaji_to_kana(Kana::Hiragana), "しんぶん");
+    assert_eq!("matcha".romaji_to_kana(Kana::Hiragana), "まっちゃ");
+    assert_eq!("tyuusya".romaji_to_kana(Kana::Hiragana), "ちゅうしゃ");
+    assert_eq!("ra-men".romaji_to_kana(Kana::Katakana), "ラーメン");
+}
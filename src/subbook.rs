@@ -1,4 +1,5 @@
 use std;
+use std::collections::HashMap;
 use std::io::{Read, Seek};
 use std::io::SeekFrom;
 use std::io::SeekFrom::Start as SeekStart;
@@ -6,29 +7,124 @@ use byteorder::{ReadBytesExt, BigEndian};
 
 use jis0208;
 
-use util::{ReaderJisExt, CharWidthExt, ToJisString};
+use util::{ReadExact, ReaderJisExt, CharWidthExt, ToJisString, ToUnicodeString, SubstituteExt};
+use util::{jis_units, edit_distances};
 use canon::{CanonicalizationRules, Canonicalization, CanonicalizeExt};
+use romaji::{Kana, RomajiExt};
+use deinflect::{Deinflection, deinflect};
+use fulltext::FulltextIndex;
 
 use Error;
 use Result;
 
 #[derive(Debug, Copy, Clone)]
 struct IndexData {
+    id: u8,
     page: u32,
     length: u32,
+    avail: u8,
+    flags: u32,
     canonicalization: CanonicalizationRules
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 struct Indices {
+    global_avail: u8,
+    text: Option<IndexData>,
     menu: Option<IndexData>,
     copyright: Option<IndexData>,
+    word_kana: Option<IndexData>,
     word_asis: Option<IndexData>,
+    word_alphabet: Option<IndexData>,
+    endword_kana: Option<IndexData>,
+    endword_asis: Option<IndexData>,
+    endword_alphabet: Option<IndexData>,
+    keyword: Option<IndexData>,
+    cross: Option<IndexData>,
+    multi: Vec<IndexData>,
+    all: Vec<IndexData>,
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum Index {
-    WordAsIs
+    WordKana,
+    WordAsIs,
+    WordAlphabet,
+    EndwordKana,
+    EndwordAsIs,
+    EndwordAlphabet,
+    Keyword,
+    Cross
+}
+
+impl Index {
+    pub fn from_id(id: u8) -> Option<Index> {
+        match id {
+            0x70 => Some(Index::EndwordKana),
+            0x71 => Some(Index::EndwordAsIs),
+            0x72 => Some(Index::EndwordAlphabet),
+            0x80 => Some(Index::Keyword),
+            0x81 => Some(Index::Cross),
+            0x90 => Some(Index::WordKana),
+            0x91 => Some(Index::WordAsIs),
+            0x92 => Some(Index::WordAlphabet),
+            _ => None
+        }
+    }
+}
+
+/// An entry of the subbook index table, as returned by `Subbook::indices`.
+#[derive(Debug, Copy, Clone)]
+pub struct IndexInfo {
+    pub id: u8,
+    /// The search this index is used for, if supported.
+    pub index: Option<Index>,
+    /// First page of the index, counting from zero, or `None` if the table entry
+    /// has no valid page.
+    pub start_page: Option<u32>,
+    pub page_count: u32,
+    pub availability: u8,
+    pub flags: u32,
+    pub canonicalization: CanonicalizationRules
+}
+
+impl IndexData {
+    fn is_endword(&self) -> bool {
+        match self.id {
+            0x70...0x72 => true,
+            _ => false
+        }
+    }
+
+    fn is_kana(&self) -> bool {
+        self.id == 0x70 || self.id == 0x90
+    }
+
+    fn is_alphabet(&self) -> bool {
+        self.id == 0x72 || self.id == 0x92
+    }
+
+    /// First page of the index, counting from zero. The table counts pages from one.
+    fn start_page(&self) -> Result<u32> {
+        self.page.checked_sub(1).ok_or(Error::InvalidFormat)
+    }
+}
+
+/// How characters in search words that cannot be encoded in JIS X 0208 are handled.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum EncodingPolicy {
+    /// Fail the search with `Error::UnencodableCharacters`.
+    Strict,
+    /// Replace such characters with compatible ones first, using NFKC and, for
+    /// alphabet indices, stripping diacritics.
+    Substitute
+}
+
+/// Preprocessing applied to search words, set through `Subbook` methods.
+#[derive(Debug, Copy, Clone)]
+struct QueryOptions {
+    encoding: EncodingPolicy,
+    romaji: Option<Kana>
 }
 
 trait ReadSeek : Read + Seek { }
@@ -36,16 +132,21 @@ impl<T: Read + Seek> ReadSeek for T { }
 
 pub struct Subbook {
     io: Box<ReadSeek>,
-    indices: Indices
+    indices: Indices,
+    multi_searches: Vec<MultiSearch>,
+    query: QueryOptions,
+    /* Heading locations of the word index entries pointing to each text location */
+    headword_map: Option<HashMap<Location, Vec<Location>>>
 }
 
 impl std::fmt::Debug for Subbook {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Subbook {{ io: ..., indices: {:?} }}", self.indices)
+        write!(f, "Subbook {{ io: ..., indices: {:?}, multi_searches: {:?} }}",
+               self.indices, self.multi_searches)
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
 pub struct Location {
     pub page: u32,
     pub offset: u16
@@ -57,120 +158,1062 @@ impl Location {
     }
 }
 
+/// Search results sharing one index entry, such as all spellings listed under a reading.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SearchGroup {
+    /// The index entry name, as stored in the index.
+    pub key: String,
+    pub entries: Vec<SearchHit>
+}
+
+/// A search result, pointing to the entry text and its heading.
+///
+/// Grouped index entries carry no heading location of their own; for them the
+/// heading is the start of the entry text.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct SearchHit {
+    pub text: Location,
+    pub heading: Location
+}
+
+/// A multi search (complex search), made up of several labelled input fields.
+#[derive(Debug, Clone)]
+pub struct MultiSearch {
+    pub fields: Vec<MultiSearchField>
+}
+
+#[derive(Debug, Clone)]
+pub struct MultiSearchField {
+    pub label: String,
+    /// Location of a candidate menu for this field, readable with `Subbook::read_text`.
+    pub candidates: Option<Location>,
+    index: Option<IndexData>
+}
+
 impl Subbook {
     pub fn from_io<IO: Read+Seek+'static>(mut io: IO) -> Result<Subbook> {
         let indices = try!(Indices::read_from(&mut io));
 
+        /* Broken multi search definitions are left out rather than making the whole
+           subbook unusable */
+        let multi_searches = indices.multi.iter().filter_map(|idata| {
+            MultiSearch::read_from(&mut io, idata, indices.global_avail).ok()
+        }).collect();
+
         Ok(Subbook {
             io: Box::new(io),
-            indices: indices
+            indices: indices,
+            multi_searches: multi_searches,
+            query: QueryOptions {
+                encoding: EncodingPolicy::Strict,
+                romaji: None
+            },
+            headword_map: None
         })
     }
 
+    /// List every entry of the index table, including ones of unknown type.
+    pub fn indices(&self) -> Vec<IndexInfo> {
+        self.indices.all.iter().map(|idata| IndexInfo {
+            id: idata.id,
+            index: Index::from_id(idata.id),
+            start_page: idata.start_page().ok(),
+            page_count: idata.length,
+            availability: idata.avail,
+            flags: idata.flags,
+            canonicalization: idata.canonicalization
+        }).collect()
+    }
+
+    pub fn set_encoding_policy(&mut self, policy: EncodingPolicy) {
+        self.query.encoding = policy;
+    }
+
+    /// Convert romaji in search words for kana indices into the given kana, or
+    /// leave search words as they are with `None`.
+    pub fn set_romaji_conversion(&mut self, kana: Option<Kana>) {
+        self.query.romaji = kana;
+    }
+
     pub fn read_text(&mut self, location: Location) -> Result<Text> {
         try!(self.io.seek( SeekStart(location.page as u64 * 0x800 + location.offset as u64) ));
-        read_text(&mut self.io)
+        read_text(&mut self.io, false)
+    }
+
+    /// Read the one-line heading at `location`, as given by `SearchHit::heading`.
+    pub fn read_heading(&mut self, location: Location) -> Result<Text> {
+        try!(self.io.seek( SeekStart(location.page as u64 * 0x800 + location.offset as u64) ));
+        read_text(&mut self.io, true)
+    }
+
+    pub fn has_menu(&self) -> bool {
+        self.indices.menu.is_some()
+    }
+
+    pub fn menu(&mut self) -> Result<Text> {
+        let idata = try!(self.indices.menu.ok_or(Error::IndexNotAvailable));
+        self.read_text(Location::page(try!(idata.start_page())))
+    }
+
+    pub fn has_copyright(&self) -> bool {
+        self.indices.copyright.is_some()
+    }
+
+    pub fn copyright(&mut self) -> Result<Text> {
+        let idata = try!(self.indices.copyright.ok_or(Error::IndexNotAvailable));
+        self.read_text(Location::page(try!(idata.start_page())))
+    }
+
+    pub fn search(&mut self, index: Index, word: &str) -> Result<Vec<SearchHit>> {
+        let idata = try!(self.indices.get(index).ok_or(Error::IndexNotAvailable));
+        self.search_index(&idata, word)
+    }
+
+    /// Search like `search`, keeping the entries of each matching index group together.
+    pub fn search_grouped(&mut self, index: Index, word: &str) -> Result<Vec<SearchGroup>> {
+        let idata = try!(self.indices.get(index).ok_or(Error::IndexNotAvailable));
+        let key = try!(search_key(&idata, word, &self.query));
+        let page = try!(idata.start_page());
+        let groups = try!(search_descend(&mut self.io, page, key.as_slice()));
+
+        Ok(merge_groups(groups, idata.is_endword()))
+    }
+
+    /// Search for many words at once, returning the hits for each word in the order
+    /// given. The index is walked once for all words, reading each page at most once.
+    pub fn search_many(&mut self, index: Index, words: &[&str]) -> Result<Vec<Vec<SearchHit>>> {
+        let idata = try!(self.indices.get(index).ok_or(Error::IndexNotAvailable));
+
+        let mut keys = Vec::with_capacity(words.len());
+        for (i, word) in words.iter().enumerate() {
+            keys.push((try!(search_key(&idata, word, &self.query)), i));
+        }
+        keys.sort();
+
+        let mut results = vec![vec![]; words.len()];
+        let page = try!(idata.start_page());
+        try!(search_many_descend(&mut self.io, page, keys.as_slice(), &mut results));
+
+        Ok(results)
+    }
+
+    fn search_index(&mut self, idata: &IndexData, word: &str) -> Result<Vec<SearchHit>> {
+        let key = try!(search_key(idata, word, &self.query));
+        let page = try!(idata.start_page());
+        let groups = try!(search_descend(&mut self.io, page, key.as_slice()));
+
+        Ok(groups.into_iter().map(|(_, hit)| hit).collect())
+    }
+
+    /// Find up to `limit` index entries starting with `prefix`, in index order.
+    /// Headwords are returned as stored in the index, i.e. canonicalized.
+    pub fn search_prefix(&mut self, index: Index, prefix: &str, limit: usize)
+        -> Result<Vec<(String, Location)>>
+    {
+        let idata = try!(self.indices.get(index).ok_or(Error::IndexNotAvailable));
+        let key = try!(search_key(&idata, prefix, &self.query));
+
+        let results = try!(self.scan_index(&idata, key.as_slice(), Some(limit), |name| {
+            if name.starts_with(key.as_slice()) {
+                Scan::Keep
+            } else if name > key.as_slice() {
+                Scan::Stop
+            } else {
+                Scan::Skip
+            }
+        }));
+
+        Ok(results.into_iter().map(|(name, hit)| (name, hit.text)).collect())
+    }
+
+    /// Find index entries matching a glob pattern, where `*` matches any number of
+    /// characters and `?` matches a single character. The rest of the pattern is
+    /// canonicalized like a search word.
+    pub fn search_wildcard(&mut self, index: Index, pattern: &str)
+        -> Result<Vec<(String, SearchHit)>>
+    {
+        let idata = try!(self.indices.get(index).ok_or(Error::IndexNotAvailable));
+        let tokens = try!(parse_glob(&idata, pattern, &self.query));
+
+        /* Only entries starting with the leading literal can match */
+        let prefix = match tokens.first() {
+            Some(&GlobToken::Literal(ref literal)) => literal.clone(),
+            _ => vec![]
+        };
+
+        self.scan_index(&idata, prefix.as_slice(), None, |name| {
+            if !name.starts_with(prefix.as_slice()) {
+                if name > prefix.as_slice() { Scan::Stop } else { Scan::Skip }
+            } else if glob_match(tokens.as_slice(), name) {
+                Scan::Keep
+            } else {
+                Scan::Skip
+            }
+        })
+    }
+
+    /// Find index entries from `from` up to, but not including, `to` in index order.
+    /// Both bounds are canonicalized like search words.
+    pub fn search_range(&mut self, index: Index, from: &str, to: &str)
+        -> Result<Vec<(String, SearchHit)>>
+    {
+        let idata = try!(self.indices.get(index).ok_or(Error::IndexNotAvailable));
+        let from = try!(search_key(&idata, from, &self.query));
+        let to = try!(search_key(&idata, to, &self.query));
+
+        self.scan_index(&idata, from.as_slice(), None, |name| {
+            if name >= to.as_slice() {
+                Scan::Stop
+            } else if name >= from.as_slice() {
+                Scan::Keep
+            } else {
+                Scan::Skip
+            }
+        })
+    }
+
+    /// Walk the index in order from the leaf page where entries for `start` begin,
+    /// letting `visit` decide what to do with each entry name.
+    fn scan_index<F>(&mut self, idata: &IndexData, start: &[u8], limit: Option<usize>, mut visit: F)
+        -> Result<Vec<(String, SearchHit)>>
+        where F: FnMut(&[u8]) -> Scan
+    {
+        let page = try!(idata.start_page());
+        let leaf = match try!(find_leaf(&mut self.io, page, start)) {
+            Some(leaf) => leaf,
+            None => return Ok(vec![])
+        };
+
+        let mut walker = LeafWalker::new(leaf);
+        let mut results = vec![];
+
+        while limit.map_or(true, |limit| results.len() < limit) {
+            let (name, hit) = match try!(walker.next_entry(&mut self.io)) {
+                Some(entry) => entry,
+                None => break
+            };
+
+            match visit(name.as_slice()) {
+                Scan::Keep => results.push((decode_name(name.as_slice(), idata.is_endword()), hit)),
+                Scan::Skip => (),
+                Scan::Stop => break
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Search like `search`, reading the index lazily as results are consumed.
+    pub fn search_iter(&mut self, index: Index, word: &str) -> Result<SearchIter> {
+        let idata = try!(self.indices.get(index).ok_or(Error::IndexNotAvailable));
+        let key = try!(search_key(&idata, word, &self.query));
+        let page = try!(idata.start_page());
+        let walker = try!(find_leaf(&mut self.io, page, key.as_slice())).map(LeafWalker::new);
+
+        Ok(SearchIter {
+            subbook: self,
+            walker: walker,
+            key: key
+        })
+    }
+
+    /// Find index entries within `max_distance` edits of `word`, closest first.
+    pub fn search_fuzzy(&mut self, index: Index, word: &str, max_distance: usize)
+        -> Result<Vec<FuzzyMatch>>
+    {
+        let idata = try!(self.indices.get(index).ok_or(Error::IndexNotAvailable));
+        let key = try!(search_key(&idata, word, &self.query));
+
+        let mut found = vec![];
+        let page = try!(idata.start_page());
+        try!(fuzzy_descend(&mut self.io, page, &[], None, jis_units(key.as_slice()).as_slice(),
+                           max_distance, &mut vec![], &mut found));
+
+        /* Stable sort keeps index order within each distance */
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(found.into_iter().map(|(distance, name, hit)| FuzzyMatch {
+            headword: decode_name(name.as_slice(), idata.is_endword()),
+            hit: hit,
+            distance: distance
+        }).collect())
+    }
+
+    /// Iterate over every entry of a word index in index order.
+    pub fn headwords(&mut self, index: Index) -> Result<Headwords> {
+        let idata = try!(self.indices.get(index).ok_or(Error::IndexNotAvailable));
+        let page = try!(idata.start_page());
+        let walker = try!(find_leaf(&mut self.io, page, &[])).map(LeafWalker::new);
+
+        Ok(Headwords {
+            subbook: self,
+            walker: walker,
+            endword: idata.is_endword()
+        })
+    }
+
+    /// List the headings of the entries the word indices lead to at `location`, as
+    /// plain text. The reverse map is built on first use by walking every word index.
+    pub fn headwords_for(&mut self, location: Location) -> Result<Vec<String>> {
+        if self.headword_map.is_none() {
+            let mut map: HashMap<Location, Vec<Location>> = HashMap::new();
+
+            for &index in [Index::WordKana, Index::WordAsIs, Index::WordAlphabet].iter() {
+                let idata = match self.indices.get(index) {
+                    Some(idata) => idata,
+                    None => continue
+                };
+                let page = try!(idata.start_page());
+                let mut walker = match try!(find_leaf(&mut self.io, page, &[])) {
+                    Some(leaf) => LeafWalker::new(leaf),
+                    None => continue
+                };
+
+                while let Some((_, hit)) = try!(walker.next_entry(&mut self.io)) {
+                    let headings = map.entry(hit.text).or_insert(vec![]);
+                    if !headings.contains(&hit.heading) {
+                        headings.push(hit.heading);
+                    }
+                }
+            }
+
+            self.headword_map = Some(map);
+        }
+
+        let headings = self.headword_map.as_ref().and_then(|map| map.get(&location)).cloned()
+                                                 .unwrap_or(vec![]);
+        let mut names = vec![];
+
+        for heading in headings {
+            let name = body_plaintext(&try!(self.read_heading(heading)));
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        Ok(names)
+    }
+
+    /// Search the keyword index for entries matching every one of `words`.
+    pub fn search_keywords(&mut self, words: &[&str]) -> Result<Vec<SearchHit>> {
+        self.search_intersection(Index::Keyword, words)
+    }
+
+    /// Search the cross search index for entries matching every one of `words`.
+    pub fn search_cross(&mut self, words: &[&str]) -> Result<Vec<SearchHit>> {
+        self.search_intersection(Index::Cross, words)
+    }
+
+    fn search_intersection(&mut self, index: Index, words: &[&str]) -> Result<Vec<SearchHit>> {
+        let idata = try!(self.indices.get(index).ok_or(Error::IndexNotAvailable));
+        let mut results = None;
+
+        for word in words.iter() {
+            let hits = try!(self.search_index(&idata, word));
+            results = Some(intersect(results, hits));
+        }
+
+        Ok(results.unwrap_or(vec![]))
+    }
+
+    pub fn multi_searches(&self) -> &[MultiSearch] {
+        self.multi_searches.as_slice()
+    }
+
+    /// Run multi search number `id`, with one optional word per field. Only entries
+    /// matching every given word are returned. Giving more words than the search has
+    /// fields fails with `Error::InvalidArgument`.
+    pub fn multi_search(&mut self, id: usize, words: &[Option<&str>]) -> Result<Vec<SearchHit>> {
+        let fields = try!(self.multi_searches.get(id).ok_or(Error::IndexNotAvailable)).fields.clone();
+        if words.len() > fields.len() {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut results = None;
+
+        for (field, word) in fields.iter().zip(words.iter()) {
+            let word = match *word {
+                Some(word) if !word.is_empty() => word,
+                _ => continue
+            };
+            let idata = try!(field.index.ok_or(Error::IndexNotAvailable));
+
+            let hits = try!(self.search_index(&idata, word));
+            results = Some(intersect(results, hits));
+        }
+
+        Ok(results.unwrap_or(vec![]))
+    }
+
+    /// Find every entry whose body text contains `pattern`.
+    pub fn search_fulltext(&mut self, pattern: &str) -> Result<Vec<Location>> {
+        self.search_fulltext_by(|text| text.contains(pattern))
+    }
+
+    /// Find every entry whose body text, as plain text without markup, is accepted by
+    /// `matches`. This allows e.g. regular expression search.
+    pub fn search_fulltext_by<F: FnMut(&str) -> bool>(&mut self, mut matches: F)
+        -> Result<Vec<Location>>
+    {
+        let mut found = vec![];
+        try!(self.scan_entries(|location, text| if matches(text) { found.push(location) }));
+
+        Ok(found)
+    }
+
+    /// Build a full text index of the body text. The file sizes are recorded in the
+    /// index for detecting when it is out of date; see `Book::build_fulltext_index`.
+    pub fn build_fulltext_index(&mut self, catalog_size: u64, text_size: u64)
+        -> Result<FulltextIndex>
+    {
+        let mut index = FulltextIndex::new(catalog_size, text_size);
+        try!(self.scan_entries(|location, text| index.insert(location, text)));
+
+        Ok(index)
+    }
+
+    /// Find every entry whose body text contains `pattern` like `search_fulltext`,
+    /// reading only the entries `index` lists as candidates.
+    pub fn search_fulltext_indexed(&mut self, index: &FulltextIndex, pattern: &str)
+        -> Result<Vec<Location>>
+    {
+        let mut found = vec![];
+
+        for location in index.candidates(pattern) {
+            /* Read as leniently as the text was indexed */
+            try!(self.io.seek( SeekStart(location.page as u64 * 0x800 + location.offset as u64) ));
+            let (text, _) = try!(read_text_entry(&mut self.io, false, true));
+            if body_plaintext(&text).contains(pattern) {
+                found.push(location);
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Read the body text sequentially, calling `f` with the location and plain text of
+    /// each entry.
+    fn scan_entries<F: FnMut(Location, &str)>(&mut self, mut f: F) -> Result<()> {
+        let idata = try!(self.indices.text.ok_or(Error::IndexNotAvailable));
+        let start = try!(idata.start_page());
+        let end = (start + idata.length) as u64 * 0x800;
+        let mut pos = try!(self.io.seek( SeekStart(start as u64 * 0x800) ));
+
+        while pos < end {
+            let (text, next_entry) = try!(read_text_entry(&mut self.io, false, true));
+            let location = Location { page: (pos / 0x800) as u32, offset: (pos % 0x800) as u16 };
+
+            let plain = body_plaintext(&text);
+            if !plain.is_empty() {
+                f(location, &plain);
+            }
+
+            if next_entry {
+                /* Step back to the keyword starting the next entry */
+                pos = try!(self.io.seek( SeekFrom::Current(-4) ));
+            } else {
+                /* Skip padding after the end of text */
+                pos = try!(self.io.seek( SeekFrom::Current(0) ));
+                while pos < end && try!(self.io.read_u8()) == 0 {
+                    pos += 1;
+                }
+                try!(self.io.seek( SeekStart(pos) ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Search the word kana and as-is indices for `word` and for every dictionary form
+    /// it could be an inflection of. Only candidates with hits are returned, the
+    /// uninflected word first.
+    pub fn search_deinflected(&mut self, word: &str) -> Result<Vec<(Deinflection, Vec<SearchHit>)>> {
+        let indices: Vec<IndexData> = [Index::WordKana, Index::WordAsIs].iter()
+            .filter_map(|&index| self.indices.get(index)).collect();
+        if indices.is_empty() {
+            return Err(Error::IndexNotAvailable);
+        }
+
+        /* Rules are written in kana, so romaji has to be converted before deinflecting */
+        let word = match self.query.romaji {
+            Some(kana) => word.romaji_to_kana(kana),
+            None => word.to_string()
+        };
+
+        let mut results = vec![];
+
+        for candidate in deinflect(&word) {
+            let mut hits: Vec<SearchHit> = vec![];
+            for idata in indices.iter() {
+                for hit in try!(self.search_index(idata, &candidate.word)) {
+                    if !hits.contains(&hit) {
+                        hits.push(hit);
+                    }
+                }
+            }
+
+            if !hits.is_empty() {
+                results.push((candidate, hits));
+            }
+        }
+
+        Ok(results)
     }
+}
 
-    pub fn search(&mut self, index: Index, word: &str) -> Result<Vec<Location>> {
-        let idata = try!(match index {
-            Index::WordAsIs => &self.indices.word_asis,
-        }.ok_or(Error::IndexNotAvailable));
-        let index_page = idata.page - 1;
-        let canonical = word.canonicalize(&idata.canonicalization).to_jis_string();
+/// Iterator over the headwords of an index, created with `Subbook::headwords`.
+pub struct Headwords<'a> {
+    subbook: &'a mut Subbook,
+    walker: Option<LeafWalker>,
+    endword: bool
+}
+
+impl<'a> Iterator for Headwords<'a> {
+    type Item = Result<(String, Location)>;
+
+    fn next(&mut self) -> Option<Result<(String, Location)>> {
+        let entry = match self.walker {
+            Some(ref mut walker) => walker.next_entry(&mut self.subbook.io),
+            None => return None
+        };
+
+        match entry {
+            Ok(Some((name, hit))) => Some(Ok((decode_name(name.as_slice(), self.endword), hit.text))),
+            Ok(None) => {
+                self.walker = None;
+                None
+            },
+            Err(e) => {
+                self.walker = None;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// A headword found by `Subbook::search_fuzzy`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct FuzzyMatch {
+    /// The headword as stored in the index, i.e. canonicalized.
+    pub headword: String,
+    pub hit: SearchHit,
+    /// Edit distance between the headword and the canonicalized search word.
+    pub distance: usize
+}
 
-        try!(self.io.seek( SeekStart(index_page as u64 * 0x800 ) ));
+/// Iterator over search results, created with `Subbook::search_iter`.
+pub struct SearchIter<'a> {
+    subbook: &'a mut Subbook,
+    walker: Option<LeafWalker>,
+    key: Vec<u8>
+}
+
+impl<'a> Iterator for SearchIter<'a> {
+    type Item = Result<SearchHit>;
+
+    fn next(&mut self) -> Option<Result<SearchHit>> {
+        loop {
+            let entry = match self.walker {
+                Some(ref mut walker) => walker.next_entry(&mut self.subbook.io),
+                None => return None
+            };
+
+            match entry {
+                Ok(Some((ref name, hit))) if *name == self.key => return Some(Ok(hit)),
+                Ok(Some((ref name, _))) if *name < self.key => continue,
+                Ok(_) => {
+                    self.walker = None;
+                    return None;
+                },
+                Err(e) => {
+                    self.walker = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+enum Scan {
+    Keep,
+    Skip,
+    Stop
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum GlobToken {
+    Literal(Vec<u8>),
+    AnyChar,
+    AnyString
+}
+
+fn parse_glob(idata: &IndexData, pattern: &str, query: &QueryOptions) -> Result<Vec<GlobToken>> {
+    let mut tokens = vec![];
+    let mut literal = String::new();
+
+    for ch in pattern.chars().map(Some).chain(Some(None).into_iter()) {
+        if ch.map_or(true, |ch| ch == '*' || ch == '?') && !literal.is_empty() {
+            let key = try!(search_key(idata, literal.as_str(), query));
+            if !key.is_empty() {
+                tokens.push(GlobToken::Literal(key));
+            }
+            literal.clear();
+        }
+
+        match ch {
+            Some('*') => tokens.push(GlobToken::AnyString),
+            Some('?') => tokens.push(GlobToken::AnyChar),
+            Some(ch) => literal.push(ch),
+            None => ()
+        }
+    }
+
+    /* Endword index entries are spelled backwards, and so is the pattern */
+    if idata.is_endword() {
+        tokens.reverse();
+    }
+
+    Ok(tokens)
+}
+
+/// Match a JIS X 0208 encoded name against a glob pattern.
+fn glob_match(tokens: &[GlobToken], name: &[u8]) -> bool {
+    match tokens.split_first() {
+        None => name.is_empty(),
+        Some((&GlobToken::Literal(ref literal), rest)) => {
+            name.starts_with(literal.as_slice()) && glob_match(rest, &name[literal.len()..])
+        },
+        Some((&GlobToken::AnyChar, rest)) => name.len() >= 2 && glob_match(rest, &name[2..]),
+        Some((&GlobToken::AnyString, rest)) => {
+            (0..name.len() / 2 + 1).any(|i| glob_match(rest, &name[2 * i..]))
+        }
+    }
+}
+
+/// Convert `word` into the form it is stored in the index as.
+fn search_key(idata: &IndexData, word: &str, query: &QueryOptions) -> Result<Vec<u8>> {
+    let mut word = word.to_string();
+
+    if let (Some(kana), true) = (query.romaji, idata.is_kana()) {
+        word = word.romaji_to_kana(kana);
+    }
+
+    if query.encoding == EncodingPolicy::Substitute {
+        word = word.substitute_unencodable(idata.is_alphabet());
+    }
+
+    let mut canonical = word.canonicalize(&idata.canonicalization);
+
+    /* Endword indices are keyed by the canonicalized word spelled backwards */
+    if idata.is_endword() {
+        canonical = canonical.chars().rev().collect();
+    }
+
+    canonical.to_jis_string().map_err(|_| {
+        /* Report the characters as given rather than canonicalized */
+        let rules = &idata.canonicalization;
+        Error::UnencodableCharacters(word.chars().filter(|ch| {
+            ch.to_string().canonicalize(rules).to_jis_string().is_err()
+        }).collect())
+    })
+}
+
+fn decode_name(name: &[u8], endword: bool) -> String {
+    let decoded = name.to_unicode_string();
+
+    if endword {
+        decoded.chars().rev().collect()
+    } else {
+        decoded
+    }
+}
+
+/// Keep the hits of `prev` whose text also appears in `hits`, without duplicates.
+/// With no previous results, all of `hits` are kept.
+fn intersect(prev: Option<Vec<SearchHit>>, hits: Vec<SearchHit>) -> Vec<SearchHit> {
+    let mut out: Vec<SearchHit> = vec![];
+
+    for hit in prev.unwrap_or_else(|| hits.clone()).into_iter() {
+        if hits.iter().any(|h| h.text == hit.text) && !out.iter().any(|h| h.text == hit.text) {
+            out.push(hit);
+        }
+    }
+
+    out
+}
+
+#[derive(Debug, Copy, Clone)]
+struct PageHeader {
+    is_leaf: bool,
+    is_layer_end: bool,
+    has_groups: bool,
+    entry_len: u64,
+    entry_count: u16
+}
+
+fn read_page_header<R: Read>(io: &mut R) -> Result<PageHeader> {
+    let page_id = try!(io.read_u8());
+    let entry_len = try!(io.read_u8()) as u64;
+    let entry_count = try!(io.read_u16::<BigEndian>());
+
+    Ok(PageHeader {
+        is_leaf: page_id & 0x80 > 0,
+        is_layer_end: page_id & 0x20 > 0,
+        has_groups: page_id & 0x10 > 0,
+        entry_len: entry_len,
+        entry_count: entry_count
+    })
+}
+
+#[derive(Debug, Clone)]
+enum LeafEntry {
+    Word { name: Vec<u8>, hit: SearchHit },
+    GroupStart { name: Vec<u8> },
+    GroupMember { text: Location }
+}
+
+/// Read a page and offset pair. Pages are stored one-based, zero meaning no location.
+fn read_location<R: Read>(io: &mut R) -> Result<Option<Location>> {
+    let page = try!(io.read_u32::<BigEndian>());
+    let offset = try!(io.read_u16::<BigEndian>());
+
+    Ok(if page == 0 {
+        None
+    } else {
+        Some(Location { page: page - 1, offset: offset })
+    })
+}
 
-        search_descend(&mut self.io, canonical.as_slice())
+fn read_text_location<R: Read>(io: &mut R) -> Result<Location> {
+    read_location(io).and_then(|loc| loc.ok_or(Error::InvalidFormat))
+}
+
+/// Read text and heading locations of an index entry.
+fn read_hit<R: Read>(io: &mut R) -> Result<SearchHit> {
+    let text = try!(read_text_location(io));
+    let heading = try!(read_location(io)).unwrap_or(text);
+
+    Ok(SearchHit { text: text, heading: heading })
+}
+
+fn read_name<R: Read>(io: &mut R, len: u64) -> Result<Vec<u8>> {
+    if len % 2 != 0 {
+        return Err(Error::InvalidFormat);
+    }
+
+    Ok(try!(io.read_jis_string(len)))
+}
+
+fn read_leaf_entry<R: Read>(io: &mut R, header: &PageHeader) -> Result<LeafEntry> {
+    match (header.has_groups, header.entry_len == 0) {
+        (true, _) => {
+            let group_id = try!(io.read_u8());
+
+            match group_id {
+                0x80 => {
+                    /* Start of group */
+                    let name_len = try!(io.read_u8()) as u64;
+                    try!(io.read_u32::<BigEndian>());
+                    let name = try!(read_name(io, name_len));
+
+                    Ok(LeafEntry::GroupStart { name: name })
+                },
+                0x00 => {
+                    /* Single-entry group */
+                    let name_len = try!(io.read_u8()) as u64;
+                    let name = try!(read_name(io, name_len));
+                    let hit = try!(read_hit(io));
+
+                    Ok(LeafEntry::Word { name: name, hit: hit })
+                },
+                0xc0 => {
+                    /* Group entry */
+                    let text = try!(read_text_location(io));
+
+                    Ok(LeafEntry::GroupMember { text: text })
+                },
+                _ => Err(Error::InvalidFormat)
+            }
+        },
+        (false, true) => {
+            /* Variable length entries */
+            let name_len = try!(io.read_u8()) as u64;
+            let name = try!(read_name(io, name_len));
+            let hit = try!(read_hit(io));
+
+            Ok(LeafEntry::Word { name: name, hit: hit })
+        },
+        (false, false) => {
+            /* Fixed length entries */
+            let name = try!(read_name(io, header.entry_len));
+            let hit = try!(read_hit(io));
+
+            Ok(LeafEntry::Word { name: name, hit: hit })
+        }
+    }
+}
+
+/// Descend the index tree from `page` to the leaf page where entries for `word`
+/// begin, if any.
+fn find_leaf<IO: Read+Seek>(io: &mut IO, page: u32, word: &[u8]) -> Result<Option<u32>> {
+    try!(io.seek( SeekStart(page as u64 * 0x800) ));
+    let header = try!(read_page_header(io));
+
+    if header.is_leaf {
+        return Ok(Some(page));
+    }
+
+    /* Internal node in index tree */
+
+    for _ in 0..header.entry_count {
+        let name = try!(read_name(io, header.entry_len));
+        let child = try!(try!(io.read_u32::<BigEndian>()).checked_sub(1).ok_or(Error::InvalidFormat));
+
+        if word <= &name {
+            return find_leaf(io, child, word);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Read the separator names and child pages of an internal index page. Each child
+/// holds the entries after the previous separator, up to and including its own.
+fn read_node_entries<R: Read>(io: &mut R, header: &PageHeader) -> Result<Vec<(Vec<u8>, u32)>> {
+    let mut entries = Vec::with_capacity(header.entry_count as usize);
+
+    for _ in 0..header.entry_count {
+        let name = try!(read_name(io, header.entry_len));
+        let child = try!(try!(io.read_u32::<BigEndian>()).checked_sub(1).ok_or(Error::InvalidFormat));
+        entries.push((name, child));
+    }
+
+    Ok(entries)
+}
+
+/// Collect the entries within `max` edits of `query` under `page`, skipping subtrees
+/// whose common name prefix is already too far from the query. `lo` and `hi` bound
+/// the names found under `page`. `group` holds the name of the group still open from
+/// the previous page, empty if there is none or it was skipped.
+fn fuzzy_descend<IO: Read+Seek>(io: &mut IO, page: u32, lo: &[u16], hi: Option<&[u16]>,
+                                query: &[u16], max: usize, group: &mut Vec<u8>,
+                                out: &mut Vec<(usize, Vec<u8>, SearchHit)>) -> Result<()>
+{
+    if let Some(hi) = hi {
+        let common = lo.iter().zip(hi.iter()).take_while(|&(a, b)| a == b).count();
+        if edit_distances(&lo[..common], query).into_iter().min().unwrap_or(0) > max {
+            /* Any group left open here is too far from the query as well */
+            group.clear();
+            return Ok(());
+        }
+    }
+
+    try!(io.seek( SeekStart(page as u64 * 0x800) ));
+    let header = try!(read_page_header(io));
+
+    if header.is_leaf {
+        for _ in 0..header.entry_count {
+            let (name, hit) = match try!(read_leaf_entry(io, &header)) {
+                LeafEntry::Word { name, hit } => {
+                    group.clear();
+                    (name, hit)
+                },
+                LeafEntry::GroupStart { name } => {
+                    *group = name;
+                    continue;
+                },
+                LeafEntry::GroupMember { .. } if group.is_empty() => continue,
+                LeafEntry::GroupMember { text } => {
+                    (group.clone(), SearchHit { text: text, heading: text })
+                }
+            };
+
+            let distance = edit_distances(jis_units(name.as_slice()).as_slice(), query)[query.len()];
+            if distance <= max {
+                out.push((distance, name, hit));
+            }
+        }
+
+        return Ok(());
+    }
+
+    let mut lo = lo.to_vec();
+    for (name, child) in try!(read_node_entries(io, &header)).into_iter() {
+        let hi = jis_units(name.as_slice());
+        try!(fuzzy_descend(io, child, lo.as_slice(), Some(hi.as_slice()), query, max, group, out));
+        lo = hi;
+    }
+
+    Ok(())
+}
+
+/// Find the entries matching `word`, each with the name of its index entry or group.
+/// Entries may continue onto the following leaf pages.
+fn search_descend<IO: Read+Seek>(io: &mut IO, page: u32, word: &[u8])
+    -> Result<Vec<(Vec<u8>, SearchHit)>>
+{
+    let mut walker = match try!(find_leaf(io, page, word)) {
+        Some(leaf) => LeafWalker::new(leaf),
+        None => return Ok(vec![])
+    };
+
+    let mut results = vec![];
+
+    while let Some((name, hit)) = try!(walker.next_entry(io)) {
+        if name.as_slice() > word {
+            break;
+        } else if name == word {
+            results.push((name, hit));
+        }
+    }
+
+    Ok(results)
+}
+
+/// Gather the hits of index entries sharing a name into one group each, in the order
+/// the names first appear.
+fn merge_groups(hits: Vec<(Vec<u8>, SearchHit)>, endword: bool) -> Vec<SearchGroup> {
+    let mut names: Vec<Vec<u8>> = vec![];
+    let mut merged: Vec<SearchGroup> = vec![];
+
+    for (name, hit) in hits {
+        match names.iter().position(|other| *other == name) {
+            Some(i) => merged[i].entries.push(hit),
+            None => {
+                let key = decode_name(name.as_slice(), endword);
+                merged.push(SearchGroup { key: key, entries: vec![hit] });
+                names.push(name);
+            }
+        }
+    }
+
+    merged
+}
+
+/// Find the entries matching each of the sorted `keys` under `page`, adding them to
+/// `results` at the position given with each key. Keys are split between child pages
+/// so that every page is read only once, except for leaf pages that entries of a key
+/// continue onto.
+fn search_many_descend<IO: Read+Seek>(io: &mut IO, page: u32, keys: &[(Vec<u8>, usize)],
+                                      results: &mut [Vec<SearchHit>]) -> Result<()>
+{
+    fn matching<'a>(keys: &'a [(Vec<u8>, usize)], name: &[u8]) -> &'a [(Vec<u8>, usize)] {
+        let start = keys.iter().take_while(|&&(ref key, _)| key.as_slice() < name).count();
+        let len = keys[start..].iter().take_while(|&&(ref key, _)| key.as_slice() == name).count();
+        &keys[start..start + len]
+    }
+
+    try!(io.seek( SeekStart(page as u64 * 0x800) ));
+    let header = try!(read_page_header(io));
+
+    if !header.is_leaf {
+        let mut rest = keys;
+
+        for (name, child) in try!(read_node_entries(io, &header)).into_iter() {
+            let n = rest.iter().take_while(|&&(ref key, _)| key <= &name).count();
+            if n > 0 {
+                try!(search_many_descend(io, child, &rest[..n], results));
+                rest = &rest[n..];
+            }
+        }
+
+        return Ok(());
+    }
+
+    /* Keep reading the following leaf pages while they may hold entries for a key */
+    let last = match keys.last() {
+        Some(&(ref key, _)) => key.as_slice(),
+        None => return Ok(())
+    };
+    let mut walker = LeafWalker::new(page);
+
+    while let Some((name, hit)) = try!(walker.next_entry(io)) {
+        if name.as_slice() > last {
+            break;
+        }
+
+        for &(_, i) in matching(keys, name.as_slice()).iter() {
+            results[i].push(hit);
+        }
     }
-}
 
-fn search_descend<IO: Read+Seek>(io: &mut IO, word: &[u8])
-    -> Result<Vec<Location>>
-{
-    let page_id = try!(io.read_u8());
-    let entry_len = try!(io.read_u8()) as u64;
-    let entry_count = try!(io.read_u16::<BigEndian>());
+    Ok(())
+}
 
-    let is_leaf = page_id & 0x80 > 0;
-    let has_groups = page_id & 0x10 > 0;
-    let has_variable_arrangement = entry_len == 0;
+/// Cursor over the entries of consecutive leaf pages of an index, in index order.
+/// Members of a group are reported under the name of the group.
+struct LeafWalker {
+    page: u32,
+    position: u64,
+    header: Option<PageHeader>,
+    remaining: u16,
+    group: Vec<u8>
+}
 
-    if is_leaf {
-        /* Leaf page with links to content */
+impl LeafWalker {
+    fn new(leaf: u32) -> LeafWalker {
+        LeafWalker {
+            page: leaf,
+            position: leaf as u64 * 0x800,
+            header: None,
+            remaining: 0,
+            group: vec![]
+        }
+    }
 
-        let mut results = vec![];
-        let mut matched = false;
-
-        for _ in 0..entry_count {
-            match (has_groups, has_variable_arrangement) {
-                (true, _) => {
-                    let group_id = try!(io.read_u8());
-
-                    match group_id {
-                        0x80 => {
-                            /* Start of group */
-                            let name_len = try!(io.read_u8()) as u64;
-                            try!(io.read_u32::<BigEndian>());
-                            let name = try!(io.read_jis_string(name_len));
-
-                            if name == word {
-                                matched = true;
-                            } else {
-                                matched = false;
-                            }
-                        },
-                        0x00 => {
-                            /* Single-entry group */
-                            unimplemented!()
-                        },
-                        0xc0 => {
-                            /* Group entry */
-                            let text_page = try!(io.read_u32::<BigEndian>())-1;
-                            let text_offs = try!(io.read_u16::<BigEndian>());
-
-                            if matched {
-                                results.push(Location { page: text_page, offset: text_offs });
-                            }
-                        },
-                        _ => panic!("unexpected group_id {}", group_id)
+    fn next_entry<IO: Read+Seek>(&mut self, io: &mut IO) -> Result<Option<(Vec<u8>, SearchHit)>> {
+        loop {
+            let header = match self.header {
+                Some(header) if self.remaining > 0 => header,
+                Some(header) if header.is_layer_end => return Ok(None),
+                previous => {
+                    if previous.is_some() {
+                        self.page += 1;
                     }
-                },
-                (false, true) => {
-                    let name_len = try!(io.read_u8()) as u64;
-                    let name = try!(io.read_jis_string(name_len));
-                    let text_page = try!(io.read_u32::<BigEndian>())-1;
-                    let text_offs = try!(io.read_u16::<BigEndian>());
-                    let _head_page = try!(io.read_u32::<BigEndian>());
-                    let _head_offs = try!(io.read_u16::<BigEndian>());
-
-                    if name == word {
-                        results.push(Location { page: text_page, offset: text_offs });
+
+                    try!(io.seek( SeekStart(self.page as u64 * 0x800) ));
+                    let header = try!(read_page_header(io));
+                    if !header.is_leaf {
+                        return Err(Error::InvalidFormat);
                     }
-                },
-                (false, false) => unimplemented!()
-            }
-        }
 
-        return Ok(results);
-    } else {
-        /* Internal node in index tree */
+                    self.position = self.page as u64 * 0x800 + 4;
+                    self.header = Some(header);
+                    self.remaining = header.entry_count;
+                    continue;
+                }
+            };
 
-        for _ in 0..entry_count {
-            let name = try!(io.read_jis_string(entry_len));
-            let page = try!(io.read_u32::<BigEndian>()) - 1;
+            try!(io.seek( SeekStart(self.position) ));
+            let entry = try!(read_leaf_entry(io, &header));
+            self.position = try!(io.seek( SeekFrom::Current(0) ));
+            self.remaining -= 1;
 
-            if word <= &name {
-                try!(io.seek( SeekStart(page as u64 * 0x800) ));
-                return search_descend(io, word);
+            match entry {
+                LeafEntry::Word { name, hit } => return Ok(Some((name, hit))),
+                LeafEntry::GroupStart { name } => self.group = name,
+                LeafEntry::GroupMember { text } => {
+                    return Ok(Some((self.group.clone(), SearchHit { text: text, heading: text })));
+                }
             }
         }
-
-        return Ok(vec![]);
     }
 }
 
 impl Indices {
+    fn get(&self, index: Index) -> Option<IndexData> {
+        match index {
+            Index::WordKana => self.word_kana,
+            Index::WordAsIs => self.word_asis,
+            Index::WordAlphabet => self.word_alphabet,
+            Index::EndwordKana => self.endword_kana,
+            Index::EndwordAsIs => self.endword_asis,
+            Index::EndwordAlphabet => self.endword_alphabet,
+            Index::Keyword => self.keyword,
+            Index::Cross => self.cross,
+        }
+    }
+
     fn read_from<R: Read+Seek>(io: &mut R) -> Result<Indices> {
         try!(io.seek(SeekStart(1)));
         let n_indices = try!(io.read_u8());
@@ -180,89 +1223,34 @@ impl Indices {
         if global_avail > 0x02 { global_avail = 0x00; }
 
         let mut ics = Indices {
-            menu: None, copyright: None, word_asis: None
+            global_avail: global_avail,
+            text: None, menu: None, copyright: None,
+            word_kana: None, word_asis: None, word_alphabet: None,
+            endword_kana: None, endword_asis: None, endword_alphabet: None,
+            keyword: None, cross: None,
+            multi: vec![],
+            all: vec![]
         };
 
         for i in 0..n_indices {
             try!(io.seek(SeekStart(16 + i as u64 * 16)));
 
-            let index_id = try!(io.read_u8());
-            try!(io.seek(SeekFrom::Current(1)));
-            let start_page = try!(io.read_u32::<BigEndian>());
-            let page_count = try!(io.read_u32::<BigEndian>());
-            let avail = try!(io.read_u8());
-            let mut flags = 0u32;
-            flags |= (try!(io.read_u8()) as u32) << 16;
-            flags |= (try!(io.read_u8()) as u32) << 8;
-            flags |= (try!(io.read_u8()) as u32) << 0;
-
-            let space_canonicalization = if index_id == 0x72 || index_id == 0x92 {
-                Canonicalization::AsIs
-            } else {
-                Canonicalization::Delete
-            };
-
-            macro_rules! canon(($mask:expr, $shift:expr) => (
-                try!(Canonicalization::from_field(((flags & $mask) >> $shift) as u8)
-                                      .ok_or(Error::InvalidFormat))
-            ));
-
-            let canonicalization =
-                if (global_avail == 0x00 || avail == 0x02) || global_avail == 0x02 {
-                    CanonicalizationRules {
-                        katakana: canon!(0xc00000, 22),
-                        lower: canon!(0x300000, 20),
-                        mark: if ((flags & 0x0c0000) >> 18) == 0 {
-                            Canonicalization::Delete
-                        } else {
-                            Canonicalization::AsIs
-                        },
-                        long_vowel: canon!(0x030000, 16),
-                        double_consonant: canon!(0x00c000, 14),
-                        contracted_sound: canon!(0x003000, 12),
-                        small_vowel: canon!(0x000c00, 10),
-                        voiced_consonant: canon!(0x000300, 8),
-                        p_sound: canon!(0x0000c0, 6),
-                        space: space_canonicalization
-                    }
-                } else if index_id == 0x70 || index_id == 0x90 {
-                    CanonicalizationRules {
-                        katakana: Canonicalization::Convert,
-                        lower: Canonicalization::Convert,
-                        mark: Canonicalization::Delete,
-                        long_vowel: Canonicalization::Convert,
-                        double_consonant: Canonicalization::Convert,
-                        contracted_sound: Canonicalization::Convert,
-                        small_vowel: Canonicalization::Convert,
-                        voiced_consonant: Canonicalization::Convert,
-                        p_sound: Canonicalization::Convert,
-                        space: space_canonicalization
-                    }
-                } else {
-                    CanonicalizationRules {
-                        katakana: Canonicalization::AsIs,
-                        lower: Canonicalization::Convert,
-                        mark: Canonicalization::AsIs,
-                        long_vowel: Canonicalization::AsIs,
-                        double_consonant: Canonicalization::AsIs,
-                        contracted_sound: Canonicalization::AsIs,
-                        small_vowel: Canonicalization::AsIs,
-                        voiced_consonant: Canonicalization::AsIs,
-                        p_sound: Canonicalization::AsIs,
-                        space: space_canonicalization
-                    }
-                };
-
-            let loc = IndexData {
-                page: start_page,
-                length: page_count,
-                canonicalization: canonicalization
-            };
+            let (index_id, loc) = try!(read_index_entry(io, global_avail));
+            ics.all.push(loc);
 
             match index_id {
+                0x00 => ics.text = Some(loc),
                 0x01 => ics.menu = Some(loc),
                 0x02 => ics.copyright = Some(loc),
+                0x70 => ics.endword_kana = Some(loc),
+                0x71 => ics.endword_asis = Some(loc),
+                0x72 => ics.endword_alphabet = Some(loc),
+                0x80 => ics.keyword = Some(loc),
+                0x81 => ics.cross = Some(loc),
+                0x90 => ics.word_kana = Some(loc),
                 0x91 => ics.word_asis = Some(loc),
+                0x92 => ics.word_alphabet = Some(loc),
+                0xff => ics.multi.push(loc),
                 _ => ()
             }
         }
@@ -271,6 +1259,136 @@ impl Indices {
     }
 }
 
+impl MultiSearch {
+    fn read_from<R: Read+Seek>(io: &mut R, idata: &IndexData, global_avail: u8)
+        -> Result<MultiSearch>
+    {
+        let base = try!(idata.start_page()) as u64 * 0x800;
+
+        try!(io.seek(SeekStart(base)));
+        let n_fields = try!(io.read_u16::<BigEndian>());
+
+        let mut offset = 16;
+        let mut fields = Vec::with_capacity(n_fields as usize);
+
+        for _ in 0..n_fields {
+            try!(io.seek(SeekStart(base + offset)));
+
+            let n_indices = try!(io.read_u8());
+            try!(io.seek(SeekFrom::Current(1)));
+            let label = try!(io.convert_jis_string(30));
+            offset += 32;
+
+            let mut field = MultiSearchField {
+                label: label,
+                candidates: None,
+                index: None
+            };
+
+            for _ in 0..n_indices {
+                try!(io.seek(SeekStart(base + offset)));
+                let (index_id, loc) = try!(read_index_entry(io, global_avail));
+                offset += 16;
+
+                match index_id {
+                    _ if loc.page == 0 => (),
+                    0x01 => field.candidates = Some(Location::page(loc.page - 1)),
+                    /* Prefer forward indices over the endword one */
+                    0x71 if field.index.is_none() => field.index = Some(loc),
+                    0x91 | 0xa1 if field.index.map_or(true, |i| i.is_endword()) => {
+                        field.index = Some(loc)
+                    },
+                    _ => ()
+                }
+            }
+
+            fields.push(field);
+        }
+
+        Ok(MultiSearch { fields: fields })
+    }
+}
+
+/// Read a 16 byte index table entry, as found in the subbook index table and
+/// in multi search definitions.
+fn read_index_entry<R: Read+Seek>(io: &mut R, global_avail: u8) -> Result<(u8, IndexData)> {
+    let index_id = try!(io.read_u8());
+    try!(io.seek(SeekFrom::Current(1)));
+    let start_page = try!(io.read_u32::<BigEndian>());
+    let page_count = try!(io.read_u32::<BigEndian>());
+    let avail = try!(io.read_u8());
+    let mut flags = 0u32;
+    flags |= (try!(io.read_u8()) as u32) << 16;
+    flags |= (try!(io.read_u8()) as u32) << 8;
+    flags |= (try!(io.read_u8()) as u32) << 0;
+
+    let space_canonicalization = if index_id == 0x72 || index_id == 0x92 {
+        Canonicalization::AsIs
+    } else {
+        Canonicalization::Delete
+    };
+
+    macro_rules! canon(($mask:expr, $shift:expr) => (
+        try!(Canonicalization::from_field(((flags & $mask) >> $shift) as u8)
+                              .ok_or(Error::InvalidFormat))
+    ));
+
+    let canonicalization =
+        if (global_avail == 0x00 || avail == 0x02) || global_avail == 0x02 {
+            CanonicalizationRules {
+                katakana: canon!(0xc00000, 22),
+                lower: canon!(0x300000, 20),
+                mark: if ((flags & 0x0c0000) >> 18) == 0 {
+                    Canonicalization::Delete
+                } else {
+                    Canonicalization::AsIs
+                },
+                long_vowel: canon!(0x030000, 16),
+                double_consonant: canon!(0x00c000, 14),
+                contracted_sound: canon!(0x003000, 12),
+                small_vowel: canon!(0x000c00, 10),
+                voiced_consonant: canon!(0x000300, 8),
+                p_sound: canon!(0x0000c0, 6),
+                space: space_canonicalization
+            }
+        } else if index_id == 0x70 || index_id == 0x90 {
+            CanonicalizationRules {
+                katakana: Canonicalization::Convert,
+                lower: Canonicalization::Convert,
+                mark: Canonicalization::Delete,
+                long_vowel: Canonicalization::Convert,
+                double_consonant: Canonicalization::Convert,
+                contracted_sound: Canonicalization::Convert,
+                small_vowel: Canonicalization::Convert,
+                voiced_consonant: Canonicalization::Convert,
+                p_sound: Canonicalization::Convert,
+                space: space_canonicalization
+            }
+        } else {
+            CanonicalizationRules {
+                katakana: Canonicalization::AsIs,
+                lower: Canonicalization::Convert,
+                mark: Canonicalization::AsIs,
+                long_vowel: Canonicalization::AsIs,
+                double_consonant: Canonicalization::AsIs,
+                contracted_sound: Canonicalization::AsIs,
+                small_vowel: Canonicalization::AsIs,
+                voiced_consonant: Canonicalization::AsIs,
+                p_sound: Canonicalization::AsIs,
+                space: space_canonicalization
+            }
+        };
+
+    Ok((index_id, IndexData {
+        id: index_id,
+        page: start_page,
+        length: page_count,
+        avail: avail,
+        flags: flags,
+        canonicalization: canonicalization
+    }))
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum TextElement {
     UnicodeString(String),
@@ -280,12 +1398,46 @@ pub enum TextElement {
     NoNewline(bool),
     BeginDecoration(u16),
     EndDecoration,
+    BeginCandidate,
+    /// End of a multi search candidate. Candidates with a location lead to a
+    /// further candidate menu, others can be used as the field value as-is.
+    EndCandidate(Option<Location>),
     Unsupported(&'static str)
 }
 
 pub type Text = Vec<TextElement>;
 
-fn read_text<R: Read>(io: &mut R) -> Result<Text> {
+fn read_text<R: Read>(io: &mut R, heading: bool) -> Result<Text> {
+    read_text_entry(io, heading, false).map(|(text, _)| text)
+}
+
+/// Name and operand length in bytes of a control code that is known but not decoded.
+fn unsupported_code(code: u8) -> Option<(&'static str, u64)> {
+    Some(match code {
+        0x12 => ("em", 0),
+        0x13 => ("/em", 0),
+        0x14 => ("color", 2),
+        0x1a | 0x1b | 0x1e | 0x1f => ("tab", 2),
+        0x32 => ("graphic ref", 0),
+        0x39 => ("mpeg", 44),
+        0x3c => ("inline color graphic", 18),
+        0x44 => ("monochrome graphic", 10),
+        0x4a => ("sound", 16),
+        0x4d => ("color graphic", 18),
+        0x52 => ("/graphic ref", 6),
+        0x59 => ("/mpeg", 0),
+        0x5c => ("/inline color graphic", 0),
+        0x64 => ("/monochrome graphic", 6),
+        0x6a => ("/sound", 0),
+        0x6d => ("/color graphic", 6),
+        _ => return None
+    })
+}
+
+/// Read text like `read_text`, also telling whether reading stopped at the start of the
+/// next entry, in which case its keyword code has already been consumed. Unless
+/// `lenient` is set, unknown control codes are an error.
+fn read_text_entry<R: Read>(io: &mut R, heading: bool, lenient: bool) -> Result<(Text, bool)> {
     let mut text = Vec::new();
 
     let mut is_narrow = false;
@@ -310,7 +1462,8 @@ fn read_text<R: Read>(io: &mut R) -> Result<Text> {
                     0x07 => text.push(TextElement::Unsupported("/sub")),
                     // Indent
                     0x09 => text.push(TextElement::Indent(try!(io.read_u16::<BigEndian>()))),
-                    // Newline
+                    // Newline, also ends a heading
+                    0x0a if heading => break,
                     0x0a => text.push(TextElement::Newline),
                     // Superscript
                     0x0e => text.push(TextElement::Unsupported("sup")),
@@ -325,13 +1478,15 @@ fn read_text<R: Read>(io: &mut R) -> Result<Text> {
                         let keyword = try!(io.read_u16::<BigEndian>());
                         if delimiter_keyword == Some(keyword) {
                             // Next entry encountered, stop.
-                            break;
+                            return Ok((text, true));
                         } else if delimiter_keyword.is_none() {
                             delimiter_keyword = Some(keyword);
                         }
                     },
                     // Begin reference
                     0x42 => text.push(TextElement::Unsupported("ref")),
+                    // Begin candidate
+                    0x43 => text.push(TextElement::BeginCandidate),
                     // End keyword
                     0x61 => (),
                     // End reference
@@ -339,10 +1494,21 @@ fn read_text<R: Read>(io: &mut R) -> Result<Text> {
                         try!(io.read_u32::<BigEndian>()); try!(io.read_u16::<BigEndian>());
                         text.push(TextElement::Unsupported("/ref"));
                     }
+                    // End candidate
+                    0x63 => text.push(TextElement::EndCandidate(try!(read_location(io)))),
                     0xe0 => text.push(TextElement::BeginDecoration(try!(io.read_u16::<BigEndian>()))),
                     0xe1 => text.push(TextElement::EndDecoration),
 
-                    x => { println!("0x{:x}", x); return Err(Error::InvalidFormat) }
+                    code => match unsupported_code(code) {
+                        Some((name, operands)) => {
+                            try!(io.read_exact_(operands));
+                            text.push(TextElement::Unsupported(name));
+                        },
+                        /* The operands of an unknown code cannot be skipped, so this
+                           is only good enough for searching */
+                        None if lenient => (),
+                        None => return Err(Error::InvalidFormat)
+                    }
                 }
             },
             _ => {
@@ -368,7 +1534,22 @@ fn read_text<R: Read>(io: &mut R) -> Result<Text> {
         }
     }
 
-    Ok(text)
+    Ok((text, false))
+}
+
+/// Text content of an entry for full text search, leaving out markup.
+fn body_plaintext(text: &Text) -> String {
+    let mut out = String::new();
+
+    for elem in text.iter() {
+        match *elem {
+            TextElement::UnicodeString(ref s) => out.push_str(&s),
+            TextElement::Newline => out.push('\n'),
+            _ => ()
+        }
+    }
+
+    out
 }
 
 pub trait ToPlaintext {
@@ -392,6 +1573,8 @@ impl ToPlaintext for Text {
                 TextElement::NoNewline(_mode) => (),
                 TextElement::BeginDecoration(_deco) => (),
                 TextElement::EndDecoration => (),
+                TextElement::BeginCandidate => (),
+                TextElement::EndCandidate(_) => (),
                 TextElement::Unsupported(name) => out.push_str(&format!("<{}>", name))
             }
         }
@@ -399,3 +1582,652 @@ impl ToPlaintext for Text {
         out
     }
 }
+
+#[cfg(test)]
+fn put(data: &mut Vec<u8>, at: usize, bytes: &[u8]) {
+    data[at..at + bytes.len()].copy_from_slice(bytes);
+}
+
+/// Write a variable length leaf page entry, with the same text and heading location.
+#[cfg(test)]
+fn put_word(data: &mut Vec<u8>, at: &mut usize, name: &str, page: u32, offset: u16) {
+    let name = name.to_jis_string().unwrap();
+    data[*at] = name.len() as u8;
+    put(data, *at + 1, &name);
+    *at += 1 + name.len();
+    /* Text and heading locations */
+    for _ in 0..2 {
+        put(data, *at, &[(page >> 24) as u8, (page >> 16) as u8, (page >> 8) as u8, page as u8,
+                         (offset >> 8) as u8, offset as u8]);
+        *at += 6;
+    }
+}
+
+/// Build subbook data with a single leaf page index for each `(id, entries)`, in order
+/// from page 2 on. Entries are index names, as stored, and one-based text pages.
+#[cfg(test)]
+fn test_indices_data(indices: &[(u8, &[(&str, u32)])]) -> Vec<u8> {
+    let mut data = vec![0u8; (indices.len() + 1) * 0x800];
+    data[1] = indices.len() as u8;
+    /* Use the default canonicalization of each index type */
+    data[4] = 0x01;
+
+    for (i, &(id, entries)) in indices.iter().enumerate() {
+        put(&mut data, 16 + i * 16, &[id, 0, 0, 0, 0, i as u8 + 2, 0, 0, 0, 1]);
+
+        let mut entries = entries.to_vec();
+        entries.sort_by_key(|&(name, _)| name.to_jis_string().unwrap());
+
+        let page = (i + 1) * 0x800;
+        put(&mut data, page, &[0xa0, 0, 0, entries.len() as u8]);
+        let mut at = page + 4;
+        for &(name, text) in entries.iter() {
+            put_word(&mut data, &mut at, name, text, 0);
+        }
+    }
+
+    data
+}
+
+/// Text locations of search hits, in order.
+#[cfg(test)]
+fn texts(hits: Vec<SearchHit>) -> Vec<Location> {
+    hits.into_iter().map(|hit| hit.text).collect()
+}
+
+/// Build subbook data with a word as-is index whose group ＡＡＡ starts on the first
+/// of two leaf pages and continues on the second.
+#[cfg(test)]
+fn test_grouped_subbook_data() -> Vec<u8> {
+    let mut data = vec![0u8; 4 * 0x800];
+
+    /* Index table: word as-is index at pages 2-4 */
+    data[1] = 1;
+    put(&mut data, 16, &[0x91, 0, 0, 0, 0, 2, 0, 0, 0, 3]);
+
+    /* Root page */
+    put(&mut data, 0x800, &[0x00, 6, 0, 2]);
+    let mut at = 0x800 + 4;
+    for &(name, child) in [("ＡＡＡ", 3u8), ("ＺＺＺ", 4u8)].iter() {
+        put(&mut data, at, &name.to_jis_string().unwrap());
+        put(&mut data, at + 6, &[0, 0, 0, child]);
+        at += 10;
+    }
+
+    /* Leaf pages */
+    put(&mut data, 0x1000, &[0x90, 0, 0, 2, 0x80, 6, 0, 0, 0, 0]);
+    put(&mut data, 0x1000 + 10, &"ＡＡＡ".to_jis_string().unwrap());
+    put(&mut data, 0x1000 + 16, &[0xc0, 0, 0, 0, 11, 0, 0]);
+
+    put(&mut data, 0x1800, &[0xb0, 0, 0, 2, 0xc0, 0, 0, 0, 12, 0, 0]);
+    let mut at = 0x1800 + 12;
+    put_word(&mut data, &mut at, "ＢＢＢ", 13, 0);
+
+    data
+}
+
+/// Build a subbook with a word as-is index of two leaf pages in memory.
+#[cfg(test)]
+fn test_subbook() -> Subbook {
+    Subbook::from_io(std::io::Cursor::new(test_subbook_data())).unwrap()
+}
+
+#[cfg(test)]
+fn test_subbook_data() -> Vec<u8> {
+    let mut data = vec![0u8; 4 * 0x800];
+
+    /* Index table: word as-is index at pages 2-4 */
+    data[1] = 1;
+    put(&mut data, 16, &[0x91, 0, 0, 0, 0, 2, 0, 0, 0, 3]);
+
+    /* Root page */
+    put(&mut data, 0x800, &[0x00, 8, 0, 2]);
+    let mut at = 0x800 + 4;
+    for &(name, child) in [("ＢＢ", 3u8), ("ＺＺ", 4u8)].iter() {
+        put(&mut data, at, &name.to_jis_string().unwrap());
+        put(&mut data, at + 8, &[0, 0, 0, child]);
+        at += 12;
+    }
+
+    /* Leaf pages */
+    put(&mut data, 0x1000, &[0x80, 0, 0, 2]);
+    let mut at = 0x1000 + 4;
+    put_word(&mut data, &mut at, "ＡＡ", 11, 0);
+    put_word(&mut data, &mut at, "ＢＢ", 12, 0);
+
+    put(&mut data, 0x1800, &[0xa0, 0, 0, 2]);
+    let mut at = 0x1800 + 4;
+    put_word(&mut data, &mut at, "ＢＢＢ", 13, 0);
+    put_word(&mut data, &mut at, "ＣＣ", 14, 0);
+
+    data
+}
+
+#[test]
+fn test_word_indices() {
+    use std::io::Cursor;
+
+    let data = test_indices_data(&[(0x90, &[("かつこう", 11)]),
+                                   (0x91, &[("ガッコウ", 12)]),
+                                   (0x92, &[("ＳＣＨＯＯＬ", 13)])]);
+    let mut sbook = Subbook::from_io(Cursor::new(data)).unwrap();
+
+    assert_eq!(texts(sbook.search(Index::WordKana, "ガッコウ").unwrap()), [Location::page(10)]);
+    assert_eq!(texts(sbook.search(Index::WordAsIs, "ガッコウ").unwrap()), [Location::page(11)]);
+    assert_eq!(texts(sbook.search(Index::WordAlphabet, "school").unwrap()), [Location::page(12)]);
+    assert!(sbook.search(Index::WordAlphabet, "ガッコウ").unwrap().is_empty());
+    match sbook.search(Index::EndwordKana, "ガッコウ") {
+        Err(Error::IndexNotAvailable) => (),
+        r => panic!("unexpected result {:?}", r)
+    }
+}
+
+#[test]
+fn test_menu_copyright() {
+    use std::io::Cursor;
+    use self::TextElement::*;
+
+    let mut data = vec![0u8; 3 * 0x800];
+
+    /* Index table: menu at page 2, copyright at page 3 */
+    data[1] = 2;
+    put(&mut data, 16, &[0x01, 0, 0, 0, 0, 2, 0, 0, 0, 1]);
+    put(&mut data, 32, &[0x02, 0, 0, 0, 0, 3, 0, 0, 0, 1]);
+
+    for &(page, text) in [(1, "メニュー"), (2, "ちょさくけん")].iter() {
+        let mut body = vec![0x1f, 0x02];
+        body.extend(text.to_jis_string().unwrap());
+        body.extend([0x1f, 0x03].iter().cloned());
+        put(&mut data, page * 0x800, &body);
+    }
+
+    let mut sbook = Subbook::from_io(Cursor::new(data)).unwrap();
+
+    assert!(sbook.has_menu() && sbook.has_copyright());
+    assert_eq!(sbook.menu().unwrap(), [UnicodeString("メニュー".to_string())]);
+    assert_eq!(sbook.copyright().unwrap(), [UnicodeString("ちょさくけん".to_string())]);
+}
+
+#[test]
+fn test_read_heading() {
+    use std::io::Cursor;
+    use self::TextElement::*;
+
+    let mut data = test_indices_data(&[(0x91, &[("ＡＡ", 3)])]);
+    data.resize(3 * 0x800, 0);
+
+    let mut body = vec![0x1f, 0x02, 0x1f, 0x41, 0x00, 0x01];
+    body.extend("ＡＡ".to_jis_string().unwrap());
+    body.extend([0x1f, 0x61, 0x1f, 0x0a].iter().cloned());
+    body.extend("ＸＹ".to_jis_string().unwrap());
+    body.extend([0x1f, 0x03].iter().cloned());
+    put(&mut data, 0x1000, &body);
+
+    let mut sbook = Subbook::from_io(Cursor::new(data)).unwrap();
+    let hit = sbook.search(Index::WordAsIs, "aa").unwrap()[0];
+
+    assert_eq!(sbook.read_heading(hit.heading).unwrap(), [UnicodeString("ＡＡ".to_string())]);
+    assert_eq!(sbook.read_text(hit.text).unwrap(),
+               [UnicodeString("ＡＡ".to_string()), Newline, UnicodeString("ＸＹ".to_string())]);
+}
+
+#[test]
+fn test_unencodable_search() {
+    use std::io::Cursor;
+
+    let data = test_indices_data(&[(0x92, &[("ＣＡＦＥ", 11)])]);
+    let mut sbook = Subbook::from_io(Cursor::new(data)).unwrap();
+
+    match sbook.search(Index::WordAlphabet, "café") {
+        Err(Error::UnencodableCharacters(chars)) => assert_eq!(chars, ['é']),
+        r => panic!("unexpected result {:?}", r)
+    }
+
+    sbook.set_encoding_policy(EncodingPolicy::Substitute);
+    assert_eq!(texts(sbook.search(Index::WordAlphabet, "café").unwrap()), [Location::page(10)]);
+}
+
+#[test]
+fn test_search_deinflected() {
+    use std::io::Cursor;
+
+    /* Kana index names are stored canonicalized */
+    let data = test_indices_data(&[(0x90, &[("たへる", 20), ("のむ", 22)]),
+                                   (0x91, &[("たべる", 20)])]);
+    let mut sbook = Subbook::from_io(Cursor::new(data)).unwrap();
+
+    let found = |sbook: &mut Subbook, word: &str| {
+        sbook.search_deinflected(word).unwrap().into_iter().map(|(candidate, hits)| {
+            (candidate.word, candidate.rules, hits.into_iter().map(|hit| hit.text.page).collect())
+        }).collect::<Vec<(String, Vec<&str>, Vec<u32>)>>()
+    };
+
+    /* Hits found in both indices are returned once */
+    assert_eq!(found(&mut sbook, "たべなかった"),
+               [("たべる".to_string(), vec!["past", "negative"], vec![19])]);
+    assert_eq!(found(&mut sbook, "のみます"), [("のむ".to_string(), vec!["polite"], vec![21])]);
+    assert_eq!(found(&mut sbook, "たべる"), [("たべる".to_string(), vec![], vec![19])]);
+    assert!(found(&mut sbook, "よんだ").is_empty());
+}
+
+#[test]
+fn test_endword_indices() {
+    use std::io::Cursor;
+
+    /* Endword names are stored canonicalized and spelled backwards */
+    let data = test_indices_data(&[(0x70, &[("いせよし", 11), ("いせんか", 12), ("いか", 13)]),
+                                   (0x71, &[("ウコッガ", 14)]),
+                                   (0x72, &[("ＳＳＥＲＴＳ", 15)])]);
+    let mut sbook = Subbook::from_io(Cursor::new(data)).unwrap();
+
+    assert_eq!(texts(sbook.search(Index::EndwordKana, "ジョセイ").unwrap()), [Location::page(10)]);
+    assert_eq!(texts(sbook.search(Index::EndwordAsIs, "ガッコウ").unwrap()), [Location::page(13)]);
+    assert_eq!(texts(sbook.search(Index::EndwordAlphabet, "stress").unwrap()), [Location::page(14)]);
+
+    /* Words ending in a suffix, returned in forward spelling */
+    assert_eq!(sbook.search_prefix(Index::EndwordKana, "せい", 10).unwrap(),
+               [("しよせい".to_string(), Location::page(10)),
+                ("かんせい".to_string(), Location::page(11))]);
+}
+
+#[test]
+fn test_search_keywords() {
+    use std::io::Cursor;
+
+    let data = test_indices_data(&[(0x80, &[("ＥＮＶＩＲＯＮＭＥＮＴＡＬ", 11),
+                                            ("ＥＮＶＩＲＯＮＭＥＮＴＡＬ", 12),
+                                            ("ＳＴＲＥＳＳ", 12),
+                                            ("ＳＴＲＥＳＳ", 13)])]);
+    let mut sbook = Subbook::from_io(Cursor::new(data)).unwrap();
+
+    assert_eq!(texts(sbook.search_keywords(&["environmental", "stress"]).unwrap()),
+               [Location::page(11)]);
+    assert_eq!(texts(sbook.search_keywords(&["stress"]).unwrap()),
+               [Location::page(11), Location::page(12)]);
+    assert!(sbook.search_keywords(&["environmental", "noise"]).unwrap().is_empty());
+}
+
+#[test]
+fn test_search_cross() {
+    use std::io::Cursor;
+
+    /* Only a cross search index; keyword search is not available */
+    let data = test_indices_data(&[(0x81, &[("がつこう", 11), ("がつこう", 12),
+                                            ("ＳＣＨＯＯＬ", 12), ("ＳＣＨＯＯＬ", 13)])]);
+    let mut sbook = Subbook::from_io(Cursor::new(data)).unwrap();
+
+    assert_eq!(texts(sbook.search_cross(&["がつこう", "school"]).unwrap()), [Location::page(11)]);
+    assert_eq!(texts(sbook.search(Index::Cross, "school").unwrap()),
+               [Location::page(11), Location::page(12)]);
+    assert!(sbook.search_keywords(&["school"]).is_err());
+}
+
+#[test]
+fn test_search_prefix() {
+    let mut sbook = test_subbook();
+
+    assert_eq!(sbook.search(Index::WordAsIs, "bb").unwrap(),
+               [SearchHit { text: Location::page(11), heading: Location::page(11) }]);
+    assert_eq!(sbook.search_prefix(Index::WordAsIs, "bb", 10).unwrap(),
+               [("ＢＢ".to_string(), Location::page(11)),
+                ("ＢＢＢ".to_string(), Location::page(12))]);
+    assert_eq!(sbook.search_prefix(Index::WordAsIs, "b", 1).unwrap().len(), 1);
+}
+
+#[test]
+fn test_search_many() {
+    let mut sbook = test_subbook();
+    let words = ["cc", "bb", "zz", "aa", "bb", "bbb"];
+
+    let results = sbook.search_many(Index::WordAsIs, &words).unwrap();
+    assert_eq!(results[0], [SearchHit { text: Location::page(13), heading: Location::page(13) }]);
+    assert!(results[2].is_empty());
+    for (word, hits) in words.iter().zip(results.iter()) {
+        assert_eq!(&sbook.search(Index::WordAsIs, word).unwrap(), hits);
+    }
+}
+
+#[test]
+fn test_search_split_group() {
+    use std::io::Cursor;
+
+    let mut sbook = Subbook::from_io(Cursor::new(test_grouped_subbook_data())).unwrap();
+
+    let hits = sbook.search(Index::WordAsIs, "aaa").unwrap();
+    assert_eq!(texts(hits.clone()), [Location::page(10), Location::page(11)]);
+    let iter_hits: Vec<SearchHit> = sbook.search_iter(Index::WordAsIs, "aaa").unwrap()
+                                         .map(|hit| hit.unwrap()).collect();
+    assert_eq!(iter_hits, hits);
+    assert_eq!(texts(sbook.search(Index::WordAsIs, "bbb").unwrap()), [Location::page(12)]);
+}
+
+#[test]
+fn test_search_grouped() {
+    use std::io::Cursor;
+
+    let mut sbook = Subbook::from_io(Cursor::new(test_grouped_subbook_data())).unwrap();
+
+    let member = |page| SearchHit { text: Location::page(page), heading: Location::page(page) };
+    assert_eq!(sbook.search_grouped(Index::WordAsIs, "aaa").unwrap(),
+               [SearchGroup { key: "ＡＡＡ".to_string(), entries: vec![member(10), member(11)] }]);
+    assert!(sbook.search_grouped(Index::WordAsIs, "aab").unwrap().is_empty());
+}
+
+#[test]
+fn test_search_many_split_group() {
+    use std::io::Cursor;
+
+    let mut sbook = Subbook::from_io(Cursor::new(test_grouped_subbook_data())).unwrap();
+
+    let results = sbook.search_many(Index::WordAsIs, &["bbb", "aaa"]).unwrap();
+    assert_eq!(texts(results[0].clone()), [Location::page(12)]);
+    assert_eq!(texts(results[1].clone()), [Location::page(10), Location::page(11)]);
+}
+
+#[test]
+fn test_search_iter() {
+    let mut sbook = test_subbook();
+
+    let hits: Vec<SearchHit> = sbook.search_iter(Index::WordAsIs, "bbb").unwrap()
+                                    .map(|hit| hit.unwrap()).collect();
+    assert_eq!(hits.iter().map(|hit| hit.text).collect::<Vec<_>>(), [Location::page(12)]);
+    assert_eq!(sbook.search_iter(Index::WordAsIs, "bc").unwrap().count(), 0);
+}
+
+#[test]
+fn test_search_fuzzy() {
+    let mut sbook = test_subbook();
+
+    let found = sbook.search_fuzzy(Index::WordAsIs, "bc", 1).unwrap();
+    assert_eq!(found.iter().map(|m| (&m.headword[..], m.distance)).collect::<Vec<_>>(),
+               [("ＢＢ", 1), ("ＣＣ", 1)]);
+
+    let found = sbook.search_fuzzy(Index::WordAsIs, "bbb", 1).unwrap();
+    assert_eq!(found.iter().map(|m| (&m.headword[..], m.distance)).collect::<Vec<_>>(),
+               [("ＢＢＢ", 0), ("ＢＢ", 1)]);
+}
+
+#[test]
+fn test_fuzzy_descend() {
+    use std::io::Cursor;
+
+    let jis = |name: &str| name.to_jis_string().unwrap();
+    let mut data = vec![0; 6 * 0x800];
+
+    /* Root page; the subtree between ＸＸＡ and ＸＸＹ is pruned for the query, so its
+       page is never read */
+    put(&mut data, 0, &[0x00, 6, 0, 5]);
+    for (i, &(name, child)) in [("ＡＡＡ", 2u8), ("ＡＡＢ", 3), ("ＸＸＡ", 4), ("ＸＸＹ", 5),
+                                ("ＺＺＺ", 6)].iter().enumerate() {
+        put(&mut data, 4 + i * 10, &jis(name));
+        put(&mut data, 4 + i * 10 + 6, &[0, 0, 0, child]);
+    }
+
+    let mut group_start = vec![0x80, 6, 0, 0, 0, 0];
+    group_start.extend(jis("ＡＡＡ"));
+    let mut word = vec![0x00, 6];
+    word.extend(jis("ＡＡＢ"));
+    word.extend([0, 0, 0, 13, 0, 0, 0, 0, 0, 0, 0, 0].iter().cloned());
+
+    /* The group ＡＡＡ continues on the second leaf page */
+    put(&mut data, 0x800, &[0x90, 0, 0, 2]);
+    put(&mut data, 0x800 + 4, &group_start);
+    put(&mut data, 0x800 + 16, &[0xc0, 0, 0, 0, 11, 0, 0]);
+    put(&mut data, 0x1000, &[0x90, 0, 0, 2, 0xc0, 0, 0, 0, 12, 0, 0]);
+    put(&mut data, 0x1000 + 11, &word);
+
+    let mut group_start = vec![0x80, 6, 0, 0, 0, 0];
+    group_start.extend(jis("ＸＸＡ"));
+    put(&mut data, 0x1800, &[0x90, 0, 0, 2]);
+    put(&mut data, 0x1800 + 4, &group_start);
+    put(&mut data, 0x1800 + 16, &[0xc0, 0, 0, 0, 14, 0, 0]);
+    put(&mut data, 0x2000, &[0x90, 0, 0, 1, 0x42]);
+
+    /* Members of the group on the pruned page are skipped */
+    let mut word = vec![0x00, 6];
+    word.extend(jis("ＺＡＡ"));
+    word.extend([0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0].iter().cloned());
+    put(&mut data, 0x2800, &[0x90, 0, 0, 2, 0xc0, 0, 0, 0, 15, 0, 0]);
+    put(&mut data, 0x2800 + 11, &word);
+
+    let mut found = vec![];
+    fuzzy_descend(&mut Cursor::new(data), 0, &[], None, jis_units(&jis("ＡＡＡ")).as_slice(), 1,
+                  &mut vec![], &mut found).unwrap();
+
+    assert_eq!(found.into_iter().map(|(distance, name, hit)| {
+        (distance, decode_name(name.as_slice(), false), hit.text.page)
+    }).collect::<Vec<_>>(), [(0, "ＡＡＡ".to_string(), 10), (0, "ＡＡＡ".to_string(), 11),
+                             (1, "ＡＡＢ".to_string(), 12), (1, "ＺＡＡ".to_string(), 15)]);
+}
+
+#[test]
+fn test_search_wildcard_range() {
+    let mut sbook = test_subbook();
+
+    let names = |found: Vec<(String, SearchHit)>| found.into_iter().map(|f| f.0).collect::<Vec<_>>();
+
+    assert_eq!(names(sbook.search_wildcard(Index::WordAsIs, "b*").unwrap()), ["ＢＢ", "ＢＢＢ"]);
+    assert_eq!(names(sbook.search_wildcard(Index::WordAsIs, "?b").unwrap()), ["ＢＢ"]);
+    assert_eq!(names(sbook.search_wildcard(Index::WordAsIs, "*c").unwrap()), ["ＣＣ"]);
+    assert_eq!(names(sbook.search_range(Index::WordAsIs, "ab", "c").unwrap()), ["ＢＢ", "ＢＢＢ"]);
+}
+
+#[test]
+fn test_headwords() {
+    let mut sbook = test_subbook();
+
+    let names: Vec<String> = sbook.headwords(Index::WordAsIs).unwrap()
+                                  .map(|hw| hw.unwrap().0).collect();
+    assert_eq!(names, ["ＡＡ", "ＢＢ", "ＢＢＢ", "ＣＣ"]);
+}
+
+#[test]
+fn test_headwords_for() {
+    use std::io::Cursor;
+
+    /* Both indices lead to the same entry under canonicalized names */
+    let mut data = test_indices_data(&[(0x90, &[("かつこう", 4)]), (0x91, &[("ガッコウ", 4)])]);
+    data.resize(4 * 0x800, 0);
+
+    let mut body = vec![0x1f, 0x02, 0x1f, 0x41, 0x00, 0x01];
+    body.extend("ガッコウ".to_jis_string().unwrap());
+    body.extend([0x1f, 0x61, 0x1f, 0x0a].iter().cloned());
+    body.extend("ＸＹ".to_jis_string().unwrap());
+    body.extend([0x1f, 0x03].iter().cloned());
+    put(&mut data, 0x1800, &body);
+
+    let mut sbook = Subbook::from_io(Cursor::new(data)).unwrap();
+
+    assert_eq!(sbook.headwords_for(Location::page(3)).unwrap(), ["ガッコウ"]);
+    assert!(sbook.headwords_for(Location::page(20)).unwrap().is_empty());
+}
+
+#[test]
+fn test_leaf_layouts() {
+    use std::io::Cursor;
+
+    let hit = SearchHit { text: Location { page: 1, offset: 2 }, heading: Location { page: 3, offset: 4 } };
+    let positions = [0, 0, 0, 2, 0, 2, 0, 0, 0, 4, 0, 4];
+
+    let fixed = PageHeader { is_leaf: true, is_layer_end: true, has_groups: false,
+                             entry_len: 4, entry_count: 1 };
+    let mut data = b"#A\x00\x00".to_vec();
+    data.extend(positions.iter().cloned());
+    match read_leaf_entry(&mut Cursor::new(data), &fixed).unwrap() {
+        LeafEntry::Word { name, hit: h } => { assert_eq!(name, b"#A"); assert_eq!(h, hit); },
+        e => panic!("unexpected entry {:?}", e)
+    }
+
+    let grouped = PageHeader { has_groups: true, entry_len: 0, .. fixed };
+    let mut data = b"\x00\x02#A".to_vec();
+    data.extend(positions.iter().cloned());
+    match read_leaf_entry(&mut Cursor::new(data), &grouped).unwrap() {
+        LeafEntry::Word { name, hit: h } => { assert_eq!(name, b"#A"); assert_eq!(h, hit); },
+        e => panic!("unexpected entry {:?}", e)
+    }
+
+    match read_leaf_entry(&mut Cursor::new(b"\x42".to_vec()), &grouped) {
+        Err(Error::InvalidFormat) => (),
+        r => panic!("unexpected result {:?}", r)
+    }
+}
+
+#[test]
+fn test_search_descend_groups() {
+    use std::io::Cursor;
+
+    let mut data = vec![
+        0xb0, 0, 0, 6,
+        0x80, 2, 0, 0, 0, 0, b'#', b'A',
+        0xc0, 0, 0, 0, 1, 0, 0,
+        0xc0, 0, 0, 0, 2, 0, 0,
+        0x00, 2, b'#', b'A', 0, 0, 0, 4, 0, 0, 0, 0, 0, 4, 0, 0,
+        0x80, 2, 0, 0, 0, 0, b'#', b'B',
+        0xc0, 0, 0, 0, 3, 0, 0
+    ];
+    data.resize(0x800, 0);
+
+    let hits = search_descend(&mut Cursor::new(data), 0, b"#A").unwrap();
+
+    assert!(hits.iter().all(|&(ref name, _)| name == b"#A"));
+    assert_eq!(texts(hits.iter().map(|&(_, hit)| hit).collect()),
+               [Location::page(0), Location::page(1), Location::page(3)]);
+
+    let groups = merge_groups(hits, false);
+
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].key, "Ａ");
+    assert_eq!(groups[0].entries.iter().map(|hit| hit.text).collect::<Vec<_>>(),
+               [Location::page(0), Location::page(1), Location::page(3)]);
+}
+
+#[test]
+fn test_indices() {
+    let sbook = test_subbook();
+    let indices = sbook.indices();
+
+    assert_eq!(indices.len(), 1);
+    assert_eq!(indices[0].id, 0x91);
+    assert_eq!(indices[0].index, Some(Index::WordAsIs));
+    assert_eq!((indices[0].start_page, indices[0].page_count), (Some(1), 3));
+
+    /* A menu entry without a valid page */
+    let mut data = test_subbook_data();
+    data[1] = 2;
+    put(&mut data, 32, &[0x01, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    let mut sbook = Subbook::from_io(std::io::Cursor::new(data)).unwrap();
+
+    assert_eq!(sbook.indices()[1].start_page, None);
+    assert!(sbook.has_menu());
+    match sbook.menu() {
+        Err(Error::InvalidFormat) => (),
+        r => panic!("unexpected result {:?}", r)
+    }
+}
+
+#[test]
+fn test_multi_search() {
+    use std::io::Cursor;
+    use self::TextElement::*;
+
+    let mut data = test_subbook_data();
+    data.resize(7 * 0x800, 0);
+
+    /* Index table: a multi search at page 6 and a broken one without a page */
+    data[1] = 3;
+    put(&mut data, 32, &[0xff, 0, 0, 0, 0, 6, 0, 0, 0, 1]);
+    put(&mut data, 48, &[0xff, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+    /* Two fields: one searching the word as-is index, one with a custom character in
+       its label and a candidate menu at page 7 */
+    put(&mut data, 0x2800, &[0, 2]);
+    put(&mut data, 0x2800 + 16, &[1, 0]);
+    put(&mut data, 0x2800 + 18, &"なまえ".to_jis_string().unwrap());
+    put(&mut data, 0x2800 + 48, &[0x91, 0, 0, 0, 0, 2, 0, 0, 0, 3]);
+    put(&mut data, 0x2800 + 64, &[1, 0, 0x7f, 0x21]);
+    put(&mut data, 0x2800 + 68, &"Ｘ".to_jis_string().unwrap());
+    put(&mut data, 0x2800 + 96, &[0x01, 0, 0, 0, 0, 7, 0, 0, 0, 1]);
+
+    let mut menu = vec![0x1f, 0x02];
+    for &(name, page) in [("ＡＡ", 0u8), ("ＢＢ", 7u8)].iter() {
+        menu.extend([0x1f, 0x43].iter().cloned());
+        menu.extend(name.to_jis_string().unwrap());
+        menu.extend([0x1f, 0x63, 0, 0, 0, page, 0, 0].iter().cloned());
+    }
+    menu.extend([0x1f, 0x03].iter().cloned());
+    put(&mut data, 0x3000, &menu);
+
+    let mut sbook = Subbook::from_io(Cursor::new(data)).unwrap();
+
+    assert_eq!(sbook.multi_searches().len(), 1);
+    let fields = sbook.multi_searches()[0].fields.clone();
+    assert_eq!(fields.iter().map(|f| &f.label[..]).collect::<Vec<_>>(), ["なまえ", "\u{fffd}Ｘ"]);
+    assert_eq!((fields[0].candidates, fields[1].candidates), (None, Some(Location::page(6))));
+
+    assert_eq!(sbook.read_text(Location::page(6)).unwrap(),
+               [BeginCandidate, UnicodeString("ＡＡ".to_string()), EndCandidate(None),
+                BeginCandidate, UnicodeString("ＢＢ".to_string()), EndCandidate(Some(Location::page(6)))]);
+
+    assert_eq!(sbook.multi_search(0, &[Some("bb"), None]).unwrap(),
+               [SearchHit { text: Location::page(11), heading: Location::page(11) }]);
+    assert!(sbook.multi_search(0, &[None, Some("x")]).is_err());
+    assert!(sbook.multi_search(1, &[Some("bb")]).is_err());
+    match sbook.multi_search(0, &[Some("bb"), None, None]) {
+        Err(Error::InvalidArgument) => (),
+        r => panic!("unexpected result {:?}", r)
+    }
+}
+
+#[test]
+fn test_search_fulltext() {
+    use std::io::Cursor;
+
+    let mut data = vec![0u8; 2 * 0x800];
+
+    /* Index table: body text at page 2 */
+    data[1] = 1;
+    data[16..26].copy_from_slice(&[0x00, 0, 0, 0, 0, 2, 0, 0, 0, 1]);
+
+    /* The graphic operands look like the start of an entry if not skipped, and the
+       unknown code in the last entry only makes a scan skip it */
+    let mut graphic = vec![0x1f, 0x3c, 0x1f, 0x41, 0x00, 0x01];
+    graphic.resize(20, 0);
+
+    let mut body = vec![0x1f, 0x02];
+    for &(head, markup, text) in [("ＡＡ", &[][..], "ＸＹ"), ("ＢＢ", &[][..], "ＺＺ"),
+                                  ("ＣＣ", &[0x1f, 0x7f][..], "ＷＷ")].iter() {
+        body.extend([0x1f, 0x41, 0x00, 0x01].iter().cloned());
+        body.extend(head.to_jis_string().unwrap());
+        body.extend([0x1f, 0x61, 0x1f, 0x0a].iter().cloned());
+        body.extend(markup.iter().cloned());
+        body.extend(text.to_jis_string().unwrap());
+        if head == "ＢＢ" {
+            body.extend(graphic.iter().cloned());
+        }
+    }
+    body.extend([0x1f, 0x03].iter().cloned());
+    data[0x800..0x800 + body.len()].copy_from_slice(&body);
+
+    let mut sbook = Subbook::from_io(Cursor::new(data)).unwrap();
+
+    assert_eq!(sbook.search_fulltext("ＸＹ").unwrap(), [Location::page(1)]);
+    let found = sbook.search_fulltext("ＺＺ").unwrap();
+    assert_eq!(found, [Location { page: 1, offset: 18 }]);
+    let text = sbook.read_text(found[0]).unwrap();
+    assert_eq!(text[0], TextElement::UnicodeString("ＢＢ".to_string()));
+    assert_eq!(text.last(), Some(&TextElement::Unsupported("inline color graphic")));
+    assert_eq!(sbook.search_fulltext_by(|text| text.starts_with("ＡＡ\nＸ")).unwrap().len(), 1);
+    assert!(sbook.search_fulltext("ＡＢ").unwrap().is_empty());
+
+    let unknown = sbook.search_fulltext("ＷＷ").unwrap();
+    assert_eq!(unknown, [Location { page: 1, offset: 54 }]);
+    match sbook.read_text(unknown[0]) {
+        Err(Error::InvalidFormat) => (),
+        r => panic!("unexpected result {:?}", r)
+    }
+
+    let index = sbook.build_fulltext_index(0, 0).unwrap();
+    assert_eq!(sbook.search_fulltext_indexed(&index, "ＺＺ").unwrap(), found);
+    assert_eq!(sbook.search_fulltext_indexed(&index, "ＷＷ").unwrap(), unknown);
+    assert!(sbook.search_fulltext_indexed(&index, "ＡＢ").unwrap().is_empty());
+}
@@ -1,9 +1,12 @@
+use std;
 use jis0208;
 use unicode_hfwidth;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
 use byteorder;
 
 use std::io::Read;
-use byteorder::{ReadBytesExt, LittleEndian};
+use byteorder::{ReadBytesExt, LittleEndian, BigEndian};
 
 pub type BoResult<T> = Result<T, byteorder::Error>;
 
@@ -20,6 +23,9 @@ impl<T: Read> ReadExact for T {
 
         while read < len {
             let bytes = try!(self.read(&mut buf[read..]));
+            if bytes == 0 {
+                return Err(byteorder::Error::UnexpectedEOF);
+            }
             read += bytes;
         }
 
@@ -29,7 +35,7 @@ impl<T: Read> ReadExact for T {
 
 pub trait ReaderJisExt {
     fn read_jis_string(&mut self, len: u64) -> BoResult<Vec<u8>>;
-    fn convert_jis_string(&mut self, len: u64) -> BoResult<Option<String>>;
+    fn convert_jis_string(&mut self, len: u64) -> BoResult<String>;
 }
 
 impl<T: Read> ReaderJisExt for T {
@@ -51,15 +57,17 @@ impl<T: Read> ReaderJisExt for T {
         Ok(data)
     }
 
-    fn convert_jis_string(&mut self, len: u64) -> BoResult<Option<String>> {
+    /// Decode a NUL-terminated JIS X 0208 string of `len` bytes, replacing code points
+    /// that cannot be decoded, such as custom characters, with U+FFFD. Code points are
+    /// stored high byte first, as in text and index pages.
+    fn convert_jis_string(&mut self, len: u64) -> BoResult<String> {
         assert_eq!(len % 2, 0);
 
         let mut string = String::with_capacity(len as usize / 2);
         let mut done = false;
-        let mut err = false;
 
         for _ in 0..(len / 2) {
-            let cp = try!(self.read_u16::<LittleEndian>());
+            let cp = try!(self.read_u16::<BigEndian>());
             if done { continue }
 
             if cp == 0x00 {
@@ -67,17 +75,10 @@ impl<T: Read> ReaderJisExt for T {
                 continue;
             }
 
-            match jis0208::decode_codepoint(cp) {
-                Some(cp) => string.push(cp),
-                None     => { err = true; done = true; }
-            }
+            string.push(jis0208::decode_codepoint(cp).unwrap_or('\u{fffd}'));
         }
 
-        if err {
-            Ok(None)
-        } else {
-            Ok(Some(string))
-        }
+        Ok(string)
     }
 }
 
@@ -105,22 +106,91 @@ impl CharWidthExt for char {
 }
 
 pub trait ToJisString {
-    fn to_jis_string(&self) -> Vec<u8>;
+    /// Encode into JIS X 0208, or return the characters that cannot be encoded.
+    fn to_jis_string(&self) -> Result<Vec<u8>, Vec<char>>;
 }
 
 impl ToJisString for str {
-    fn to_jis_string(&self) -> Vec<u8> {
+    fn to_jis_string(&self) -> Result<Vec<u8>, Vec<char>> {
         let mut data = Vec::with_capacity(self.chars().count() * 2);
+        let mut unencodable = vec![];
+
         for ch in self.chars() {
-            for cp in jis0208::encode_codepoint(ch).into_iter() {
-                data.push(((cp & 0xff00) >> 8) as u8);
-                data.push((cp & 0xff) as u8);
+            match jis0208::encode_codepoint(ch) {
+                Some(cp) => {
+                    data.push(((cp & 0xff00) >> 8) as u8);
+                    data.push((cp & 0xff) as u8);
+                },
+                None => unencodable.push(ch)
             }
         }
-        data
+
+        if unencodable.is_empty() {
+            Ok(data)
+        } else {
+            Err(unencodable)
+        }
+    }
+}
+
+pub trait SubstituteExt {
+    fn substitute_unencodable(&self, strip_diacritics: bool) -> String;
+}
+
+impl SubstituteExt for str {
+    /// Replace characters that have no JIS X 0208 encoding with their NFKC
+    /// decomposition, optionally dropping diacritics too. Characters that can be
+    /// encoded are left untouched.
+    fn substitute_unencodable(&self, strip_diacritics: bool) -> String {
+        let encodable = |ch: char| {
+            jis0208::encode_codepoint(ch).is_some() ||
+            jis0208::encode_codepoint(ch.to_fullwidth()).is_some()
+        };
+
+        let mut out = String::with_capacity(self.len());
+
+        for ch in self.chars() {
+            if encodable(ch) {
+                out.push(ch);
+                continue;
+            }
+
+            for sub in Some(ch).into_iter().nfkc() {
+                if strip_diacritics && !encodable(sub) {
+                    out.extend(Some(sub).into_iter().nfd().filter(|&c| !is_combining_mark(c)));
+                } else {
+                    out.push(sub);
+                }
+            }
+        }
+
+        out
     }
 }
 
+/// Split a JIS X 0208 string into its two byte code points.
+pub fn jis_units(data: &[u8]) -> Vec<u16> {
+    data.chunks(2).map(|bs| ((bs[0] as u16) << 8) | (*bs.get(1).unwrap_or(&0) as u16)).collect()
+}
+
+/// Levenshtein distances between `a` and every prefix of `b`, shortest prefix first.
+pub fn edit_distances<T: PartialEq>(a: &[T], b: &[T]) -> Vec<usize> {
+    let mut row: Vec<usize> = (0..b.len() + 1).collect();
+
+    for (i, x) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, y) in b.iter().enumerate() {
+            let substitution = diagonal + if x == y { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = std::cmp::min(substitution, std::cmp::min(row[j], row[j + 1]) + 1);
+        }
+    }
+
+    row
+}
+
 pub trait ToUnicodeString {
     fn to_unicode_string(&self) -> String;
 }
@@ -142,9 +212,35 @@ impl ToUnicodeString for [u8] {
 fn test_conversion_roundtrip() {
     let a = "ｅｎｖｉｒｏｎｍｅｎｔａｌ　ｓｔｒｅｓｓ";
 
-    let b = a.to_jis_string();
+    let b = a.to_jis_string().unwrap();
     assert_eq!(b, b"#e#n#v#i#r#o#n#m#e#n#t#a#l!!#s#t#r#e#s#s");
 
     let c = b.to_unicode_string();
     assert_eq!(c, a);
 }
+
+#[test]
+fn test_convert_jis_string() {
+    use std::io::Cursor;
+
+    let data = vec![0x24, 0x22, 0x24, 0x24, 0x00, 0x00, 0x24, 0x26];
+    assert_eq!(Cursor::new(data).convert_jis_string(8).unwrap(), "あい");
+
+    let data = vec![0x24, 0x22, 0x7f, 0x21, 0x00, 0x00, 0x24, 0x26];
+    assert_eq!(Cursor::new(data).convert_jis_string(8).unwrap(), "あ\u{fffd}");
+}
+
+#[test]
+fn test_unencodable() {
+    assert_eq!("ｃａｆé".to_jis_string(), Err(vec!['é']));
+    assert_eq!("café".substitute_unencodable(false), "café");
+    assert_eq!("café".substitute_unencodable(true), "cafe");
+    assert_eq!("No①".substitute_unencodable(false), "No1");
+}
+
+#[test]
+fn test_edit_distances() {
+    assert_eq!(edit_distances(b"kitten", b"sitting")[7], 3);
+    assert_eq!(edit_distances(b"sit", b"sitting"), [3, 2, 1, 0, 1, 2, 3, 4]);
+    assert_eq!(edit_distances(b"", b"ab"), [0, 1, 2]);
+}
@@ -60,5 +60,28 @@ fn keyword_search_test() {
     let result = sbook.search(epwing::subbook::Index::WordAsIs, "environmental stress").unwrap();
 
     assert_eq!(result.len(), 1);
-    assert_eq!(result[0], epwing::subbook::Location { page: 24561, offset: 1264 });
+    assert_eq!(result[0].text, epwing::subbook::Location { page: 24561, offset: 1264 });
+}
+
+#[test]
+fn prefix_search_test() {
+    let book = open_book();
+    let spine = &book.subbooks()[0];
+    let mut sbook = book.open_subbook(spine).unwrap();
+
+    let result = sbook.search_prefix(epwing::subbook::Index::WordAsIs, "environmental stres", 10).unwrap();
+
+    assert!(result.contains(&("ＥＮＶＩＲＯＮＭＥＮＴＡＬＳＴＲＥＳＳ".to_owned(),
+                              epwing::subbook::Location { page: 24561, offset: 1264 })));
+}
+
+#[test]
+fn search_all_test() {
+    let mut book = open_book();
+
+    let result = book.search_all(epwing::subbook::Index::WordAsIs, "environmental stress").unwrap();
+
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].subbook, 0);
+    assert_eq!(result[0].hit.text, epwing::subbook::Location { page: 24561, offset: 1264 });
 }